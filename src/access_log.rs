@@ -0,0 +1,229 @@
+/*- Imports -*/
+use std::fs::{ self, OpenOptions };
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{ Duration, SystemTime };
+use lazy_static::lazy_static;
+use crate::{ Request, utils::{ UtcTime, MONTHS } };
+
+/*- Workers share the log files, so only one may write (or rotate) at a time -*/
+lazy_static! {
+    static ref FILE_LOCK:Mutex<()> = Mutex::new(());
+}
+
+/// How each access log line is formatted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// `127.0.0.1 - - [19/Oct/2026:13:55:36 +0000] "GET / HTTP/1.1" 200 466`
+    Common,
+    /// Common, followed by the quoted referer and user agent
+    Combined,
+    /// One json object per line
+    Json,
+}
+
+/// Where access log lines are written
+#[derive(Debug, Clone)]
+pub enum LogSink {
+    Stdout,
+    /// Append to a file. When it grows past `max_bytes` it's renamed to
+    /// `path.1` (and `path.1` to `path.2` and so on), keeping `keep` old files
+    File { path:&'static str, max_bytes:u64, keep:usize },
+    /// Emit an info event with the target `fastserve::access`, so
+    /// that a tracing subscriber (or log logger) collects it
+    Tracing,
+    /// Get the entry and the formatted line
+    Custom(fn(&AccessEntry, &str)),
+}
+
+/// Per-request access logging, set through `ServerOptions::access_log`
+/// # Examples
+/// ```ignore
+/// access_log: Some(AccessLog {
+///     format: LogFormat::Combined,
+///     sink: LogSink::File { path: "./access.log", max_bytes: 10_000_000, keep: 5 },
+/// }),
+/// ```
+#[derive(Debug, Clone)]
+pub struct AccessLog {
+    pub format:LogFormat,
+    pub sink:LogSink,
+}
+
+/// Everything that is known about a finished request
+#[derive(Debug, Clone)]
+pub struct AccessEntry {
+    /// Like `GET /path?query HTTP/1.1`
+    pub request_line:String,
+    pub method:String,
+    pub path:String,
+    /// `None` if a `RouteValue::Function` wrote the response itself, or nothing was sent
+    pub status:Option<u16>,
+    /// Size of the response body
+    pub bytes:Option<usize>,
+    pub duration:Duration,
+    pub peer:Option<SocketAddr>,
+    pub user_agent:Option<String>,
+    pub referer:Option<String>,
+    pub time:SystemTime,
+}
+
+impl AccessEntry {
+    /// Collect the entry of a request, `sent` is the status and body size of the response
+    pub fn new(request:&Request, sent:Option<(u16, usize)>) -> AccessEntry {
+        let request_line = request.request_line().to_string();
+
+        AccessEntry {
+            method: request_line.split(' ').next().unwrap_or("").to_string(),
+            path: request_line.split(' ').nth(1).unwrap_or("").to_string(),
+            request_line,
+            status: sent.map(|(status, _)| status),
+            bytes: sent.map(|(_, bytes)| bytes),
+            duration: request.received.elapsed(),
+            peer: request.peer,
+            user_agent: request.headers.user_agent().map(String::from),
+            referer: request.headers.referer().map(String::from),
+            time: SystemTime::now(),
+        }
+    }
+
+    /// Format the entry as one line (without the newline)
+    pub fn format(&self, format:LogFormat) -> String {
+        let ip = self.peer.map(|p| p.ip().to_string()).unwrap_or_else(|| String::from("-"));
+        let or_dash = |value:Option<String>| value.unwrap_or_else(|| String::from("-"));
+
+        match format {
+            LogFormat::Common | LogFormat::Combined => {
+                let t = UtcTime::from(self.time);
+                let mut line = format!(
+                    "{} - - [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] \"{}\" {} {}",
+                    ip, t.day, MONTHS[t.month as usize - 1], t.year, t.hour, t.minute, t.second,
+                    self.request_line.replace('"', "\\\""),
+                    or_dash(self.status.map(|s| s.to_string())),
+                    /*- The format has `-` for an empty body, not `0` -*/
+                    or_dash(self.bytes.filter(|b| *b > 0).map(|b| b.to_string())),
+                );
+
+                if format == LogFormat::Combined {
+                    line.push_str(&format!(
+                        " \"{}\" \"{}\"",
+                        or_dash(self.referer.clone()).replace('"', "\\\""),
+                        or_dash(self.user_agent.clone()).replace('"', "\\\""),
+                    ));
+                };
+
+                line
+            },
+            LogFormat::Json => {
+                let t = UtcTime::from(self.time);
+                let optional = |value:Option<String>| value.unwrap_or_else(|| String::from("null"));
+                let quoted = |value:&Option<String>| value.as_ref().map(|v| format!("\"{}\"", json_escape(v)));
+
+                format!(
+                    "{{\"time\":\"{}-{:02}-{:02}T{:02}:{:02}:{:02}Z\",\"method\":\"{}\",\"path\":\"{}\",\"status\":{},\"bytes\":{},\"duration_ms\":{:.3},\"ip\":{},\"user_agent\":{},\"referer\":{}}}",
+                    t.year, t.month, t.day, t.hour, t.minute, t.second,
+                    json_escape(&self.method),
+                    json_escape(&self.path),
+                    optional(self.status.map(|s| s.to_string())),
+                    optional(self.bytes.map(|b| b.to_string())),
+                    self.duration.as_secs_f64() * 1000.0,
+                    optional(self.peer.map(|p| format!("\"{}\"", p.ip()))),
+                    optional(quoted(&self.user_agent)),
+                    optional(quoted(&self.referer)),
+                )
+            },
+        }
+    }
+}
+
+impl AccessLog {
+
+    /// Format and write the entry of a finished request
+    pub fn write(&self, entry:&AccessEntry) {
+        let line = entry.format(self.format);
+
+        match &self.sink {
+            LogSink::Stdout => println!("{}", line),
+            LogSink::File { path, max_bytes, keep } => {
+                let _lock = FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+                rotate(path, *max_bytes, *keep);
+
+                /*- Logging should never take the server down, so errors are ignored -*/
+                if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                    writeln!(file, "{}", line).unwrap_or(());
+                };
+            },
+            LogSink::Tracing => tracing::info!(target: "fastserve::access", "{}", line),
+            LogSink::Custom(sink) => sink(entry, &line),
+        };
+    }
+}
+
+/*- Shift path -> path.1 -> path.2 ... if the file is too large -*/
+fn rotate(path:&str, max_bytes:u64, keep:usize) {
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if max_bytes == 0 || size < max_bytes { return; };
+
+    if keep == 0 {
+        fs::remove_file(path).unwrap_or(());
+        return;
+    };
+
+    for index in (1..keep).rev() {
+        fs::rename(format!("{}.{}", path, index), format!("{}.{}", path, index + 1)).unwrap_or(());
+    };
+    fs::rename(path, format!("{}.1", path)).unwrap_or(());
+}
+
+/*- Escape a string so that it can be put inside of json quotes -*/
+pub(crate) fn json_escape(input:&str) -> String {
+    let mut output = String::with_capacity(input.len());
+
+    for c in input.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        };
+    };
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(status:Option<u16>, bytes:Option<usize>) -> AccessEntry {
+        AccessEntry {
+            request_line: String::from("GET /logo.png HTTP/1.1"),
+            method: String::from("GET"),
+            path: String::from("/logo.png"),
+            status,
+            bytes,
+            duration: Duration::from_millis(2),
+            peer: Some("127.0.0.1:50000".parse().unwrap()),
+            user_agent: None,
+            referer: Some(String::from("http://example.com/")),
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(1_760_882_136),
+        }
+    }
+
+    #[test]
+    fn empty_bodies_are_logged_as_a_dash() {
+        assert_eq!(entry(Some(304), Some(0)).format(LogFormat::Common),
+            "127.0.0.1 - - [19/Oct/2025:13:55:36 +0000] \"GET /logo.png HTTP/1.1\" 304 -");
+        assert_eq!(entry(None, None).format(LogFormat::Common),
+            "127.0.0.1 - - [19/Oct/2025:13:55:36 +0000] \"GET /logo.png HTTP/1.1\" - -");
+        assert_eq!(entry(Some(200), Some(466)).format(LogFormat::Combined),
+            "127.0.0.1 - - [19/Oct/2025:13:55:36 +0000] \"GET /logo.png HTTP/1.1\" 200 466 \"http://example.com/\" \"-\"");
+
+        /*- Json keeps the number -*/
+        assert!(entry(Some(304), Some(0)).format(LogFormat::Json).contains("\"status\":304,\"bytes\":0,"));
+    }
+}
@@ -0,0 +1,94 @@
+/*- Imports -*/
+use std::fmt;
+use std::time::Duration;
+
+/// A `Cache-Control` value for the static files matching a pattern.
+/// Patterns without a `/` are matched against the file name (`*.css`,
+/// `index.html`), the others against the whole path inside of the static
+/// dir (`/fonts/*`). `*` matches anything except `/`, `**` matches anything.
+/// # Examples
+/// ```ignore
+/// cache_rules: vec![
+///     CacheRule::new("*.*.js", CacheControl::new().public().max_age_secs(31536000).immutable()),
+///     CacheRule::new("index.html", CacheControl::new().no_cache()),
+/// ],
+/// ```
+#[derive(Debug, Clone)]
+pub struct CacheRule {
+    pub pattern:&'static str,
+    pub cache_control:String,
+}
+
+impl CacheRule {
+    pub fn new<T:Into<String>>(pattern:&'static str, cache_control:T) -> CacheRule {
+        CacheRule { pattern, cache_control: cache_control.into() }
+    }
+
+    /// If the rule applies to a path (relative to the static dir)
+    pub fn matches(&self, path:&str) -> bool {
+        if self.pattern.contains('/') {
+            let path = format!("/{}", path.trim_start_matches('/'));
+            let pattern = format!("/{}", self.pattern.trim_start_matches('/'));
+            glob(pattern.as_bytes(), path.as_bytes())
+        } else {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            glob(self.pattern.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+/*- Match a glob with * (not crossing /) and ** (anything) -*/
+fn glob(pattern:&[u8], input:&[u8]) -> bool {
+    match pattern {
+        [] => input.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=input.len()).any(|i| glob(rest, &input[i..])),
+        [b'*', rest @ ..] => {
+            let segment = input.iter().position(|&c| c == b'/').unwrap_or(input.len());
+            (0..=segment).any(|i| glob(rest, &input[i..]))
+        },
+        [c, rest @ ..] => input.first() == Some(c) && glob(rest, &input[1..]),
+    }
+}
+
+/// Builds a `Cache-Control` header value
+/// # Examples
+/// ```ignore
+/// CacheControl::new().public().max_age(Duration::from_secs(3600)).to_string()
+/// // "public, max-age=3600"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheControl {
+    directives:Vec<String>,
+}
+
+impl CacheControl {
+    pub fn new() -> CacheControl { CacheControl::default() }
+
+    fn directive(mut self, directive:String) -> CacheControl {
+        self.directives.push(directive);
+        self
+    }
+
+    pub fn public(self) -> CacheControl { self.directive(String::from("public")) }
+    pub fn private(self) -> CacheControl { self.directive(String::from("private")) }
+    pub fn no_cache(self) -> CacheControl { self.directive(String::from("no-cache")) }
+    pub fn no_store(self) -> CacheControl { self.directive(String::from("no-store")) }
+    pub fn immutable(self) -> CacheControl { self.directive(String::from("immutable")) }
+    pub fn must_revalidate(self) -> CacheControl { self.directive(String::from("must-revalidate")) }
+    pub fn max_age(self, age:Duration) -> CacheControl { self.max_age_secs(age.as_secs()) }
+    pub fn max_age_secs(self, secs:u64) -> CacheControl { self.directive(format!("max-age={}", secs)) }
+    pub fn s_maxage_secs(self, secs:u64) -> CacheControl { self.directive(format!("s-maxage={}", secs)) }
+    pub fn stale_while_revalidate_secs(self, secs:u64) -> CacheControl { self.directive(format!("stale-while-revalidate={}", secs)) }
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.directives.join(", "))
+    }
+}
+
+impl From<CacheControl> for String {
+    fn from(cache_control:CacheControl) -> String {
+        cache_control.to_string()
+    }
+}
@@ -0,0 +1,289 @@
+/*- Imports -*/
+use std::io;
+use crate::{ Request, Response };
+
+/// A content-coding the server can compress responses with. Each one
+/// is behind a cargo feature of the same name (all on by default)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl Encoding {
+
+    /// The token used in `Accept-Encoding` and `Content-Encoding`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// The extension of files compressed with this encoding
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gz",
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zst",
+        }
+    }
+
+    /// If the crate was built with the feature for this encoding
+    pub fn is_available(&self) -> bool {
+        match self {
+            Encoding::Gzip => cfg!(feature = "gzip"),
+            Encoding::Brotli => cfg!(feature = "brotli"),
+            Encoding::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    /// Compress some bytes
+    #[cfg_attr(not(all(feature = "gzip", feature = "brotli", feature = "zstd")), allow(unused_variables))]
+    pub fn compress(&self, content:&[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip => {
+                use std::io::Write;
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(content)?;
+                encoder.finish()
+            },
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => {
+                use std::io::Write;
+                let mut output = Vec::new();
+                {
+                    /*- Quality 5 is a lot faster than the max (11), for on-the-fly use -*/
+                    let mut encoder = brotli::CompressorWriter::new(&mut output, 4096, 5, 22);
+                    encoder.write_all(content)?;
+                };
+                Ok(output)
+            },
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => zstd::encode_all(content, 3),
+            #[allow(unreachable_patterns)]
+            _ => Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} support isn't enabled", self.as_str()))),
+        }
+    }
+}
+
+/// Automatic compression of responses, set through `ServerOptions::compression`
+/// # Examples
+/// ```ignore
+/// compression: Some(Compression { min_size: 512, ..Default::default() }),
+/// ```
+#[derive(Debug, Clone)]
+pub struct Compression {
+    /// The encodings to use, the first ones are preferred
+    /// if the client accepts more than one equally
+    pub encodings:Vec<Encoding>,
+    /// Bodies smaller than this (in bytes) are sent as they are
+    pub min_size:usize,
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression {
+            encodings: vec![Encoding::Brotli, Encoding::Zstd, Encoding::Gzip],
+            min_size: 1024,
+        }
+    }
+}
+
+/// Pick the encoding to use from an `Accept-Encoding` header. The
+/// highest q-value wins, ties go to the first one in `supported`.
+/// This doesn't check `Encoding::is_available`
+pub fn negotiate(accept_encoding:&str, supported:&[Encoding]) -> Option<Encoding> {
+    /*- Parse `gzip;q=0.8, br` into (coding, q) pairs -*/
+    let accepted:Vec<(String, f32)> = accept_encoding.split(',').filter_map(|part| {
+        let mut params = part.split(';');
+        let coding = params.next()?.trim().to_ascii_lowercase();
+        if coding.is_empty() { return None; };
+
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        Some((coding, q))
+    }).collect();
+
+    /*- Look for the coding itself first, otherwise a wildcard -*/
+    let quality = |encoding:&Encoding| -> f32 {
+        accepted.iter().find(|(c, _)| c == encoding.as_str())
+            .or_else(|| accepted.iter().find(|(c, _)| c == "*"))
+            .map(|(_, q)| *q)
+            .unwrap_or(0.0)
+    };
+
+    let mut best:Option<(Encoding, f32)> = None;
+    for encoding in supported.iter() {
+        let q = quality(encoding);
+        if q > 0.0 && best.map(|(_, best_q)| q > best_q).unwrap_or(true) {
+            best = Some((*encoding, q));
+        };
+    };
+
+    best.map(|(encoding, _)| encoding)
+}
+
+/// If a content type is worth compressing. Images (except svg),
+/// video, audio, woff fonts and archives are compressed already
+pub fn is_compressible(content_type:&str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+
+    mime.starts_with("text/")
+        || mime.ends_with("+xml")
+        || mime.ends_with("+json")
+        || matches!(mime.as_str(),
+            "application/json" | "application/javascript" | "application/xml"
+            | "application/wasm" | "application/yaml" | "application/toml"
+            | "application/vnd.ms-fontobject" | "font/ttf" | "font/otf"
+            | "image/svg+xml" | "image/bmp" | "image/x-icon"
+        )
+}
+
+/// Add a value to the `Vary` header, unless it's already there
+pub fn add_vary(response:&mut Response, header:&str) {
+    let vary = match response.get_header("Vary") {
+        Some(vary) if vary.split(',').any(|v| v.trim().eq_ignore_ascii_case(header) || v.trim() == "*") => return,
+        Some(vary) => format!("{}, {}", vary, header),
+        None => header.to_string(),
+    };
+
+    response.set_header("Vary", &vary);
+}
+
+/*- Compress the response if the client accepts it, and it's worth it -*/
+pub(crate) fn compress_response(compression:&Compression, request:&Request, response:&mut Response) {
+    let content_type = response.get_header("Content-Type").unwrap_or("").to_string();
+    if !is_compressible(&content_type)
+    || response.get_header("Content-Encoding").is_some()
+    || matches!(response.status, 204 | 206) {
+        return;
+    };
+
+    /*- The response depends on Accept-Encoding even when it isn't compressed. A 304
+        has no body, but it needs the Vary of the 200 it stands in for, or caches can
+        pair it with the wrong stored variant -*/
+    add_vary(response, "Accept-Encoding");
+    if response.status != 304 && response.body.len() < compression.min_size { return; };
+
+    let available:Vec<Encoding> = compression.encodings.iter().filter(|e| e.is_available()).copied().collect();
+    let encoding = match negotiate(request.header("Accept-Encoding").unwrap_or(""), &available) {
+        Some(encoding) => encoding,
+        None => return,
+    };
+
+    /*- Nothing to compress, but the ETag has to be the one the 200 would have -*/
+    if response.status == 304 { return weaken_etag(response); };
+
+    match encoding.compress(&response.body) {
+        Ok(body) => {
+            response.body = body;
+            response.set_header("Content-Encoding", encoding.as_str());
+            weaken_etag(response);
+        },
+        Err(error) => tracing::warn!(%error, encoding = encoding.as_str(), "compression failed"),
+    };
+}
+
+/*- Compressed bytes aren't the ones a strong ETag promises anymore, only the
+    content is the same. If-None-Match compares weakly, so 304s still work -*/
+fn weaken_etag(response:&mut Response) {
+    if let Some(etag) = response.get_header("ETag").filter(|etag| !etag.starts_with("W/")) {
+        let weak = format!("W/{}", etag);
+        response.set_header("ETag", &weak);
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResponseType;
+
+    const ALL:[Encoding; 3] = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+    fn request(accept_encoding:&str) -> Request {
+        Request::parse(format!("GET / HTTP/1.1\r\nAccept-Encoding: {}\r\n\r\n", accept_encoding), None)
+    }
+
+    #[test]
+    fn negotiation_follows_q_values() {
+        assert_eq!(negotiate("gzip, br", &ALL), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0.5", &ALL), Some(Encoding::Gzip));
+        assert_eq!(negotiate("GZIP", &ALL), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*;q=0.1, zstd;q=0.2", &ALL), Some(Encoding::Zstd));
+        assert_eq!(negotiate("*", &[Encoding::Gzip]), Some(Encoding::Gzip));
+
+        /*- q=0 refuses a coding, even when a wildcard would accept it -*/
+        assert_eq!(negotiate("br;q=0, *", &ALL), Some(Encoding::Zstd));
+        assert_eq!(negotiate("gzip;q=0", &ALL), None);
+        assert_eq!(negotiate("identity", &ALL), None);
+        assert_eq!(negotiate("", &ALL), None);
+    }
+
+    #[test]
+    fn compressible_types() {
+        assert!(is_compressible("text/html; charset=utf-8"));
+        assert!(is_compressible("application/ld+json"));
+        assert!(is_compressible("image/svg+xml"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("application/zip"));
+        assert!(!is_compressible(""));
+    }
+
+    #[test]
+    fn vary_values_are_not_repeated() {
+        let mut response = Response::new(200).header("Vary", "Cookie");
+        add_vary(&mut response, "Accept-Encoding");
+        add_vary(&mut response, "accept-encoding");
+        assert_eq!(response.get_header("Vary"), Some("Cookie, Accept-Encoding"));
+
+        let mut response = Response::new(200).header("Vary", "*");
+        add_vary(&mut response, "Accept-Encoding");
+        assert_eq!(response.get_header("Vary"), Some("*"));
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn compressed_responses_get_weak_etags() {
+        let compression = Compression { encodings: vec![Encoding::Gzip], min_size: 16 };
+        let body = "a compressible body ".repeat(20);
+
+        let mut response = Response::content(200, ResponseType::Text, body.clone()).header("ETag", "\"abc\"");
+        compress_response(&compression, &request("gzip"), &mut response);
+        assert_eq!(response.get_header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.get_header("ETag"), Some("W/\"abc\""));
+        assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+        assert!(response.body.len() < body.len());
+
+        /*- Small bodies are left alone, but still vary -*/
+        let mut response = Response::content(200, ResponseType::Text, "short").header("ETag", "\"abc\"");
+        compress_response(&compression, &request("gzip"), &mut response);
+        assert_eq!(response.get_header("Content-Encoding"), None);
+        assert_eq!(response.get_header("ETag"), Some("\"abc\""));
+        assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+
+        let mut response = Response::content(200, ResponseType::Text, body.clone());
+        compress_response(&compression, &request("br"), &mut response);
+        assert_eq!(response.get_header("Content-Encoding"), None);
+        assert_eq!(response.body, body.as_bytes());
+
+        /*- Not modified answers have the Vary and ETag the 200 would have -*/
+        let mut response = Response::content(304, ResponseType::Text, "").header("ETag", "\"abc\"");
+        compress_response(&compression, &request("gzip"), &mut response);
+        assert_eq!(response.get_header("Content-Encoding"), None);
+        assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.get_header("ETag"), Some("W/\"abc\""));
+        assert!(response.body.is_empty());
+
+        let mut response = Response::content(304, ResponseType::Text, "").header("ETag", "\"abc\"");
+        compress_response(&compression, &request("identity"), &mut response);
+        assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.get_header("ETag"), Some("\"abc\""));
+    }
+}
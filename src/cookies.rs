@@ -0,0 +1,280 @@
+/*- Imports -*/
+use std::fmt;
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+use percent_encoding::{ utf8_percent_encode, percent_decode_str, AsciiSet, CONTROLS };
+use crate::{ Request, Response };
+use crate::utils::http_date;
+
+/// The `SameSite` attribute of a cookie
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    /// Browsers only accept this with `Secure`, so it's added automatically
+    None,
+}
+
+/// A cookie to set with `Response::cookie`, which becomes a `Set-Cookie` header
+/// # Examples
+/// ```ignore
+/// Response::new(200).cookie(
+///     Cookie::new("theme", "dark")
+///         .path("/")
+///         .max_age(Duration::from_secs(60 * 60 * 24 * 365))
+///         .http_only()
+///         .same_site(SameSite::Lax)
+/// )
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    pub name:String,
+    pub value:String,
+    pub path:Option<String>,
+    pub domain:Option<String>,
+    pub max_age:Option<Duration>,
+    pub expires:Option<SystemTime>,
+    pub secure:bool,
+    pub http_only:bool,
+    pub same_site:Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name:&str, value:&str) -> Cookie {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    pub fn path(mut self, path:&str) -> Cookie { self.path = Some(path.to_string()); self }
+    pub fn domain(mut self, domain:&str) -> Cookie { self.domain = Some(domain.to_string()); self }
+    pub fn max_age(mut self, max_age:Duration) -> Cookie { self.max_age = Some(max_age); self }
+    pub fn expires(mut self, expires:SystemTime) -> Cookie { self.expires = Some(expires); self }
+    pub fn secure(mut self) -> Cookie { self.secure = true; self }
+    pub fn http_only(mut self) -> Cookie { self.http_only = true; self }
+    pub fn same_site(mut self, same_site:SameSite) -> Cookie { self.same_site = Some(same_site); self }
+
+    /// The same cookie, but expired, which makes the browser remove it.
+    /// The path and domain have to be the ones it was set with
+    pub fn removal(mut self) -> Cookie {
+        self.value = String::new();
+        self.max_age = Some(Duration::ZERO);
+        self.expires = Some(UNIX_EPOCH);
+        self
+    }
+}
+
+/*- Everything that isn't a cookie-octet (RFC 6265), and `%` so that decoding is unambiguous -*/
+const VALUE:&AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b',').add(b';').add(b'\\').add(b'%');
+/*- Names are tokens, which leaves out the separators too -*/
+const NAME:&AsciiSet = &VALUE.add(b'(').add(b')').add(b'<').add(b'>').add(b'@').add(b':')
+    .add(b'/').add(b'[').add(b']').add(b'?').add(b'=').add(b'{').add(b'}');
+
+/*- Leave out what could end the attribute (or the header) early -*/
+fn clean(input:&str) -> String {
+    input.chars().filter(|c| !c.is_control() && *c != ';').collect()
+}
+
+/// Formats the value of a `Set-Cookie` header. Names and values are percent-encoded
+/// where they have characters cookies can't, `CookieJar` decodes them again
+impl fmt::Display for Cookie {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", utf8_percent_encode(&self.name, NAME), utf8_percent_encode(&self.value, VALUE))?;
+
+        if let Some(path) = &self.path { write!(f, "; Path={}", clean(path))?; };
+        if let Some(domain) = &self.domain { write!(f, "; Domain={}", clean(domain))?; };
+        if let Some(max_age) = self.max_age { write!(f, "; Max-Age={}", max_age.as_secs())?; };
+        if let Some(expires) = self.expires { write!(f, "; Expires={}", http_date(expires))?; };
+        if self.secure || self.same_site == Some(SameSite::None) { f.write_str("; Secure")?; };
+        if self.http_only { f.write_str("; HttpOnly")?; };
+        match self.same_site {
+            Some(SameSite::Strict) => f.write_str("; SameSite=Strict")?,
+            Some(SameSite::Lax) => f.write_str("; SameSite=Lax")?,
+            Some(SameSite::None) => f.write_str("; SameSite=None")?,
+            None => (),
+        };
+
+        Ok(())
+    }
+}
+
+/// The cookies a request was sent with
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CookieJar {
+    pub cookies:Vec<(String, String)>,
+}
+
+impl CookieJar {
+
+    /// Parse the value of a `Cookie` header, like `theme=dark; session=abc`.
+    /// Percent-escapes in names and values are decoded
+    pub fn parse(header:&str) -> CookieJar {
+        let mut jar = CookieJar::default();
+        jar.add_header(header);
+        jar
+    }
+
+    fn add_header(&mut self, header:&str) {
+        for pair in header.split(';') {
+            if let Some((name, value)) = pair.split_once('=') {
+                let name = name.trim();
+                if name.is_empty() { continue; };
+
+                /*- Values are allowed to be quoted -*/
+                let value = value.trim();
+                let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+                let decode = |input:&str| percent_decode_str(input).decode_utf8_lossy().to_string();
+                self.cookies.push((decode(name), decode(value)));
+            };
+        };
+    }
+
+    /// The value of a cookie. Browsers send the cookie with the most specific
+    /// path first if there are more with the same name, and that's the one returned
+    pub fn get(&self, name:&str) -> Option<&str> {
+        self.cookies.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of a cookie name
+    pub fn get_all(&self, name:&str) -> Vec<&str> {
+        self.cookies.iter().filter(|(key, _)| key == name).map(|(_, value)| value.as_str()).collect()
+    }
+
+    pub fn contains(&self, name:&str) -> bool { self.get(name).is_some() }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cookies.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize { self.cookies.len() }
+    pub fn is_empty(&self) -> bool { self.cookies.is_empty() }
+}
+
+impl Request {
+
+    /// The cookies of the request, from every `Cookie` header
+    pub fn cookies(&self) -> CookieJar {
+        let mut jar = CookieJar::default();
+        for header in self.headers.get_all("Cookie") {
+            jar.add_header(header);
+        };
+
+        jar
+    }
+
+    /// The value of one cookie
+    pub fn cookie(&self, name:&str) -> Option<String> {
+        self.cookies().get(name).map(String::from)
+    }
+}
+
+impl Response {
+
+    /// Add a `Set-Cookie` header, builder style
+    pub fn cookie(self, cookie:Cookie) -> Response {
+        self.header("Set-Cookie", &cookie.to_string())
+    }
+
+    /// Tell the browser to remove a cookie. The path and domain have to be the ones it was set with
+    pub fn remove_cookie(self, cookie:Cookie) -> Response {
+        self.cookie(cookie.removal())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_values() {
+        let jar = CookieJar::parse("a=\"quoted value\"; b=\"only-start; c=\"\"");
+        assert_eq!(jar.get("a"), Some("quoted value"));
+        /*- Unbalanced quotes are kept -*/
+        assert_eq!(jar.get("b"), Some("\"only-start"));
+        assert_eq!(jar.get("c"), Some(""));
+    }
+
+    #[test]
+    fn empty_values() {
+        let jar = CookieJar::parse("empty=; other=1; =nameless");
+        assert_eq!(jar.get("empty"), Some(""));
+        assert!(jar.contains("empty"));
+        assert_eq!(jar.get("other"), Some("1"));
+        /*- Cookies need a name -*/
+        assert_eq!(jar.len(), 2);
+    }
+
+    #[test]
+    fn stray_semicolons() {
+        let jar = CookieJar::parse(";; theme=dark ;;session=abc;novalue; ");
+        assert_eq!(jar.iter().collect::<Vec<_>>(), vec![("theme", "dark"), ("session", "abc")]);
+
+        let jar = CookieJar::parse("id=1; id=2");
+        assert_eq!(jar.get("id"), Some("1"));
+        assert_eq!(jar.get_all("id"), vec!["1", "2"]);
+        assert!(CookieJar::parse("").is_empty());
+    }
+
+    #[test]
+    fn set_cookie_attributes() {
+        let cookie = Cookie::new("theme", "dark")
+            .path("/app")
+            .domain("example.com")
+            .max_age(Duration::from_secs(3600))
+            .expires(UNIX_EPOCH + Duration::from_secs(784111777))
+            .secure()
+            .http_only()
+            .same_site(SameSite::Strict);
+        assert_eq!(
+            cookie.to_string(),
+            "theme=dark; Path=/app; Domain=example.com; Max-Age=3600; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Secure; HttpOnly; SameSite=Strict"
+        );
+
+        assert_eq!(Cookie::new("a", "b").to_string(), "a=b");
+        /*- SameSite=None is only accepted with Secure -*/
+        assert_eq!(Cookie::new("a", "b").same_site(SameSite::None).to_string(), "a=b; Secure; SameSite=None");
+        assert_eq!(
+            Cookie::new("a", "b").path("/").removal().to_string(),
+            "a=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn set_cookie_can_not_be_broken_out_of() {
+        let cookie = Cookie::new("na=me;", "value\r\nSet-Cookie: x=1; Secure").path("/;Domain=evil.com");
+        assert_eq!(cookie.to_string(), "na%3Dme%3B=value%0D%0ASet-Cookie:%20x=1%3B%20Secure; Path=/Domain=evil.com");
+    }
+
+    #[test]
+    fn names_and_values_round_trip() {
+        let cookies = [
+            ("a", "x;y"),
+            ("cart", "3 apples, \"green\""),
+            ("path", "C:\\Users\\me"),
+            ("percent", "100%25 sure"),
+            ("unicode", "Ærø ✓"),
+            ("lines", "one\r\ntwo\u{7f}"),
+            ("na=me (1)", "v"),
+            ("plain", "b64/+=.-_~!#$&'*"),
+        ];
+
+        for (name, value) in cookies {
+            let header = Cookie::new(name, value).http_only().to_string();
+            let pair = header.strip_suffix("; HttpOnly").unwrap();
+            assert!(!pair.contains([';', ',', ' ', '"', '\\', '\r', '\n']), "{}", pair);
+
+            let jar = CookieJar::parse(pair);
+            assert_eq!(jar.iter().collect::<Vec<_>>(), vec![(name, value)], "{}", pair);
+        };
+
+        /*- Values that need no encoding are sent as they are -*/
+        assert_eq!(Cookie::new("plain", "b64/+=.-_~!#$&'*").to_string(), "plain=b64/+=.-_~!#$&'*");
+    }
+}
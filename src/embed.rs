@@ -0,0 +1,109 @@
+/*- Imports -*/
+use std::{ env, fs, io };
+use std::path::{ Path, PathBuf };
+use crate::utils::etag;
+
+/// A directory that was embedded into the binary at compile time,
+/// served through `Statics::embedded` instead of reading `Statics::dir`.
+/// Made by `embed_dir` in a build script and included with `embedded_dir!`
+#[derive(Debug)]
+pub struct EmbeddedDir {
+    pub files:&'static [EmbeddedFile],
+}
+
+/// One embedded file
+#[derive(Debug)]
+pub struct EmbeddedFile {
+    /// The path inside of the embedded dir, like `css/style.css`
+    pub path:&'static str,
+    pub content:&'static [u8],
+    /// Quoted hash of the content, computed at compile time
+    pub etag:&'static str,
+}
+
+impl EmbeddedDir {
+
+    /// Get a file by its path inside of the dir
+    pub fn get(&self, path:&str) -> Option<&'static EmbeddedFile> {
+        let path = path.trim_start_matches('/');
+        self.files.iter().find(|file| file.path == path)
+    }
+
+    /// If there are files inside of `path`
+    pub fn is_dir(&self, path:&str) -> bool {
+        let dir = path.trim_matches('/');
+        if dir.is_empty() { return !self.files.is_empty(); };
+
+        self.files.iter().any(|file| {
+            file.path.strip_prefix(dir).map(|rest| rest.starts_with('/')).unwrap_or(false)
+        })
+    }
+}
+
+/// Embed a directory (relative to the crate root) into the binary.
+/// Call this from `build.rs`, and include the result with `embedded_dir!`.
+/// `name` is what the generated file is called, so that more than one dir can be embedded
+/// # Examples
+/// ```ignore
+/// // build.rs, with fastserve in [build-dependencies]
+/// fn main() {
+///     fastserve::embed_dir("static", "static").unwrap();
+/// }
+///
+/// // main.rs
+/// static STATIC:fastserve::EmbeddedDir = fastserve::embedded_dir!("static");
+///
+/// statics: Statics { embedded: Some(&STATIC), ..Default::default() },
+/// ```
+pub fn embed_dir(dir:&str, name:&str) -> io::Result<()> {
+    let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap_or_else(|_| String::from("."))).join(dir);
+    let out = PathBuf::from(env::var("OUT_DIR").map_err(|_| io::Error::new(io::ErrorKind::NotFound, "OUT_DIR isn't set, embed_dir has to run in a build script"))?);
+
+    let mut files:Vec<PathBuf> = Vec::new();
+    collect_files(&root, &mut files)?;
+    files.sort();
+
+    /*- Rebuild when something in the dir changes -*/
+    println!("cargo:rerun-if-changed={}", root.display());
+
+    let mut code = String::from("fastserve::EmbeddedDir { files: &[\n");
+    for file in files.iter() {
+        println!("cargo:rerun-if-changed={}", file.display());
+
+        let relative = file.strip_prefix(&root).unwrap_or(file)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<String>>()
+            .join("/");
+        let content = fs::read(file)?;
+
+        code.push_str(&format!(
+            "    fastserve::EmbeddedFile {{ path: {:?}, content: include_bytes!({:?}), etag: {:?} }},\n",
+            relative, file.display().to_string(), etag(&content),
+        ));
+    };
+    code.push_str("] }\n");
+
+    fs::write(out.join(format!("{}.rs", name)), code)
+}
+
+/*- Every file below a dir, following the dir recursively -*/
+fn collect_files(dir:&Path, files:&mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path.is_file() {
+            files.push(path);
+        };
+    };
+    Ok(())
+}
+
+/// Include a directory that was embedded by `embed_dir` in the build script
+#[macro_export]
+macro_rules! embedded_dir {
+    ($name:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/", $name, ".rs"))
+    };
+}
@@ -0,0 +1,93 @@
+/*- Imports -*/
+use std::collections::HashMap;
+use crate::{ Request, Response, Statics, page_response };
+use crate::listing::html_escape;
+use crate::utils::{ ResponseType, status_message };
+
+/// What to send for an error status
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorPage {
+    /// A file in the static dir, like `errors/500.html`
+    File(&'static str),
+    /// Html where `{status}`, `{message}` and `{path}` are filled in
+    Template(&'static str),
+    /// Build the response yourself. It's used for json clients too
+    Function(fn(&Request, u16) -> Response),
+}
+
+/// Error pages by status, set through `ServerOptions::error_pages`. They replace
+/// the errors the server sends by itself (404, 401, 413...) and the ones handlers
+/// return without a body of their own, like `Response::new(403)`.
+///
+/// Clients preferring json (over html) get a json error instead of
+/// `File` and `Template` pages, like `{"status":404,"message":"Not Found"}`
+/// # Examples
+/// ```ignore
+/// error_pages: ErrorPages::new()
+///     .on(404, ErrorPage::File("404.html"))
+///     .on(401, ErrorPage::Function(|_, status| Response::new(status).header("WWW-Authenticate", "Basic")))
+///     .class(5, ErrorPage::Template("<h1>{status} {message}</h1><p>{path} is broken</p>")),
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ErrorPages {
+    statuses:HashMap<u16, ErrorPage>,
+    classes:HashMap<u16, ErrorPage>,
+}
+
+impl ErrorPages {
+    pub fn new() -> ErrorPages { ErrorPages::default() }
+
+    /// The page for one status
+    pub fn on(mut self, status:u16, page:ErrorPage) -> ErrorPages {
+        self.statuses.insert(status, page);
+        self
+    }
+
+    /// The page for a whole class of statuses without a page of their own, `4` for 4xx and `5` for 5xx
+    pub fn class(mut self, class:u16, page:ErrorPage) -> ErrorPages {
+        self.classes.insert(class, page);
+        self
+    }
+
+    /// The page that is used for a status
+    pub fn get(&self, status:u16) -> Option<&ErrorPage> {
+        self.statuses.get(&status).or_else(|| self.classes.get(&(status / 100)))
+    }
+
+    /// Build the error response for a status, if it has a page
+    pub fn respond(&self, request:&Request, status:u16, statics:&Statics) -> Option<Response> {
+        let page = self.get(status)?;
+        let json = prefers_json(request);
+
+        let mut response = match page {
+            ErrorPage::Function(function) => function(request, status),
+            _ if json => Response::error(status, &ResponseType::Json),
+            ErrorPage::File(path) => page_response(status, path, statics)
+                .unwrap_or_else(|| Response::error(status, &ResponseType::Html)),
+            ErrorPage::Template(template) => Response::content(status, ResponseType::Html, template
+                .replace("{status}", &status.to_string())
+                .replace("{message}", status_message(status))
+                .replace("{path}", &html_escape(&request.path))
+            ),
+        };
+
+        response.status = status;
+        Some(response)
+    }
+}
+
+/// If the `Accept` header prefers json over html. Json has to be asked for
+/// explicitly, `*/*` counts as html
+pub fn prefers_json(request:&Request) -> bool {
+    let accept = request.header("Accept").unwrap_or("").to_ascii_lowercase();
+    let position = |mime:&str| accept.split(',').position(|part| {
+        let part = part.split(';').next().unwrap_or("").trim();
+        part == mime || (mime == "application/json" && part.ends_with("+json"))
+    });
+
+    match (position("application/json"), position("text/html")) {
+        (Some(json), Some(html)) => json < html,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,327 @@
+/*- Imports -*/
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::sync::{ Arc, Mutex, MutexGuard, Weak };
+use std::thread;
+use std::time::{ Duration, SystemTime };
+
+/// How cached files are noticed to have changed on disk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Invalidation {
+    /// Check the modification time and size of the file on every hit.
+    /// Costs a `stat`, but never serves stale content
+    Mtime,
+    /// A background thread polls the cached files every `interval`, stat-ing
+    /// each of them, and hits don't touch the disk at all. Changes can take
+    /// up to `interval` to show up
+    Watch(Duration),
+}
+
+/// An LRU in-memory cache for small static files, set through `Statics::file_cache`.
+/// Clones share the same cache, so one can be used by several mounts.
+/// # Examples
+/// ```ignore
+/// statics: Statics {
+///     file_cache: Some(FileCache::new(32 * 1024 * 1024).max_file_size(512 * 1024)),
+///     ..Default::default()
+/// },
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileCache {
+    store:Arc<Mutex<Store>>,
+    /// Least recently used files are dropped when the cache grows past this
+    pub max_bytes:usize,
+    /// Larger files are always read from disk
+    pub max_file_size:usize,
+    pub invalidation:Invalidation,
+}
+
+/*- The shared state of a FileCache -*/
+#[derive(Debug, Default)]
+struct Store {
+    entries:HashMap<String, Entry>,
+    size:usize,
+    /*- Incremented on every hit, the entry with the lowest one is evicted first -*/
+    tick:u64,
+    watching:bool,
+}
+
+#[derive(Debug)]
+struct Entry {
+    content:Arc<Vec<u8>>,
+    modified:Option<SystemTime>,
+    len:u64,
+    last_used:u64,
+}
+
+impl FileCache {
+
+    /// A cache holding up to `max_bytes` of file content. Files up
+    /// to a 16th of that are cached, and invalidated by mtime
+    pub fn new(max_bytes:usize) -> FileCache {
+        FileCache {
+            store: Arc::new(Mutex::new(Store::default())),
+            max_bytes,
+            max_file_size: max_bytes / 16,
+            invalidation: Invalidation::Mtime,
+        }
+    }
+
+    /// Set the size of the largest file that is cached
+    pub fn max_file_size(mut self, max_file_size:usize) -> FileCache {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    /// Poll the cached files from a background thread every `interval`, instead of
+    /// checking their mtime on every hit. Each poll stats every cached file, and
+    /// changes can be served stale for up to `interval`
+    pub fn watch(mut self, interval:Duration) -> FileCache {
+        self.invalidation = Invalidation::Watch(interval);
+        self
+    }
+
+    /*- A poisoned lock only means that another worker panicked, the map is still fine -*/
+    fn lock(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Get the content of a file, if it's cached and hasn't changed
+    pub fn get(&self, full_path:&str) -> Option<Arc<Vec<u8>>> {
+        /*- Stat outside of the lock, so that workers don't wait on each others disk -*/
+        let metadata = match self.invalidation {
+            Invalidation::Mtime => Some(fs::metadata(full_path).ok()),
+            Invalidation::Watch(_) => None,
+        };
+
+        let mut store = self.lock();
+        store.tick += 1;
+        let tick = store.tick;
+
+        let fresh = match (store.entries.get(full_path), metadata) {
+            (None, _) => None,
+            (Some(_), None) => Some(true),
+            (Some(entry), Some(metadata)) => Some(metadata.map(|m| {
+                m.is_file() && m.len() == entry.len && m.modified().ok() == entry.modified
+            }).unwrap_or(false)),
+        };
+
+        match fresh {
+            Some(true) => {
+                let entry = store.entries.get_mut(full_path)?;
+                entry.last_used = tick;
+                Some(entry.content.clone())
+            },
+            Some(false) => {
+                store.remove(full_path);
+                None
+            },
+            None => None,
+        }
+    }
+
+    /// The content of a file, from the cache if it's there and hasn't changed,
+    /// otherwise from disk (and then cached, if it's small enough)
+    pub fn read(&self, full_path:&str) -> io::Result<Arc<Vec<u8>>> {
+        if let Some(content) = self.get(full_path) { return Ok(content); };
+
+        /*- Stat before reading. If the file is written in between, the cached
+            content is newer than the metadata, and the next check drops it.
+            The other way around, old content would be kept as the new version -*/
+        let metadata = fs::metadata(full_path)?;
+        let content = Arc::new(fs::read(full_path)?);
+        self.insert(full_path, content.clone(), &metadata);
+        Ok(content)
+    }
+
+    /*- Cache content, with the metadata the file had before it was read -*/
+    fn insert(&self, full_path:&str, content:Arc<Vec<u8>>, metadata:&fs::Metadata) {
+        if content.len() > self.max_file_size || content.len() > self.max_bytes { return; };
+
+        let mut store = self.lock();
+        store.tick += 1;
+        let size = content.len();
+        let entry = Entry {
+            content,
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            last_used: store.tick,
+        };
+        store.remove(full_path);
+        store.size += size;
+        store.entries.insert(full_path.to_string(), entry);
+
+        /*- Make room by dropping the least recently used files -*/
+        while store.size > self.max_bytes {
+            let oldest = match store.entries.iter().min_by_key(|(_, entry)| entry.last_used) {
+                Some((path, _)) => path.clone(),
+                None => break,
+            };
+            store.remove(&oldest);
+        };
+
+        if let Invalidation::Watch(interval) = self.invalidation {
+            if !store.watching {
+                store.watching = true;
+                spawn_watcher(Arc::downgrade(&self.store), interval);
+            };
+        };
+    }
+
+    /// Drop a file from the cache
+    pub fn invalidate(&self, full_path:&str) {
+        self.lock().remove(full_path);
+    }
+
+    /// Drop every file from the cache
+    pub fn clear(&self) {
+        let mut store = self.lock();
+        store.entries.clear();
+        store.size = 0;
+    }
+
+    /// How many files are cached
+    pub fn len(&self) -> usize { self.lock().entries.len() }
+
+    /// If no files are cached
+    pub fn is_empty(&self) -> bool { self.lock().entries.is_empty() }
+
+    /// How many bytes of file content are cached
+    pub fn size(&self) -> usize { self.lock().size }
+}
+
+impl Store {
+    fn remove(&mut self, full_path:&str) {
+        if let Some(entry) = self.entries.remove(full_path) {
+            self.size -= entry.content.len();
+        };
+    }
+}
+
+/*- Drop cached files that changed on disk, until the cache itself is dropped -*/
+fn spawn_watcher(store:Weak<Mutex<Store>>, interval:Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let store = match store.upgrade() {
+            Some(store) => store,
+            None => return,
+        };
+
+        /*- Copy what to check, so that the lock isn't held while stat-ing -*/
+        let cached:Vec<(String, Option<SystemTime>, u64)> = store.lock().unwrap_or_else(|e| e.into_inner())
+            .entries.iter()
+            .map(|(path, entry)| (path.clone(), entry.modified, entry.len))
+            .collect();
+
+        let changed:Vec<String> = cached.into_iter().filter(|(path, modified, len)| {
+            match fs::metadata(path) {
+                Ok(metadata) => !metadata.is_file() || metadata.len() != *len || metadata.modified().ok() != *modified,
+                Err(_) => true,
+            }
+        }).map(|(path, _, _)| path).collect();
+
+        if !changed.is_empty() {
+            let mut store = store.lock().unwrap_or_else(|e| e.into_inner());
+            for path in changed.iter() {
+                tracing::debug!(path = %path, "cached file changed");
+                store.remove(path);
+            };
+        };
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::{ Path, PathBuf };
+
+    /*- A fresh dir for each test, so that they can run in parallel -*/
+    fn test_dir(name:&str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("fastserve-file-cache-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).unwrap_or(());
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(dir:&Path, name:&str, size:usize) -> String {
+        let path = dir.join(name);
+        fs::write(&path, vec![b'x'; size]).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn eviction_keeps_the_cache_within_max_bytes() {
+        let dir = test_dir("eviction");
+        let cache = FileCache::new(100).max_file_size(40);
+        let a = write(&dir, "a", 40);
+        let b = write(&dir, "b", 40);
+        let c = write(&dir, "c", 40);
+        let large = write(&dir, "large", 41);
+
+        cache.read(&a).unwrap();
+        cache.read(&b).unwrap();
+        assert_eq!(cache.size(), 80);
+
+        /*- a was used last, so b is the one that goes -*/
+        assert!(cache.get(&a).is_some());
+        cache.read(&c).unwrap();
+        assert_eq!(cache.size(), 80);
+        assert!(cache.get(&b).is_none());
+        assert!(cache.get(&a).is_some());
+        assert!(cache.get(&c).is_some());
+
+        /*- Files over max_file_size are read, but not cached -*/
+        assert_eq!(cache.read(&large).unwrap().len(), 41);
+        assert!(cache.get(&large).is_none());
+        assert_eq!(cache.len(), 2);
+
+        fs::remove_dir_all(&dir).unwrap_or(());
+    }
+
+    #[test]
+    fn changed_files_are_dropped() {
+        let dir = test_dir("mtime");
+        let cache = FileCache::new(1024);
+        let path = write(&dir, "page.html", 10);
+
+        cache.read(&path).unwrap();
+        assert!(cache.get(&path).is_some());
+
+        /*- Same size, only the mtime changes -*/
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        assert!(cache.get(&path).is_none());
+        assert!(cache.is_empty());
+
+        cache.read(&path).unwrap();
+        fs::write(&path, "longer content").unwrap();
+        assert!(cache.get(&path).is_none());
+        assert_eq!(&cache.read(&path).unwrap()[..], b"longer content");
+
+        fs::remove_file(&path).unwrap();
+        assert!(cache.get(&path).is_none());
+        assert!(cache.read(&path).is_err());
+        assert_eq!(cache.size(), 0);
+
+        fs::remove_dir_all(&dir).unwrap_or(());
+    }
+
+    #[test]
+    fn watched_caches_poll_for_changes() {
+        let dir = test_dir("watch");
+        let cache = FileCache::new(1024).watch(Duration::from_millis(20));
+        let path = write(&dir, "app.js", 10);
+
+        cache.read(&path).unwrap();
+        fs::write(&path, "changed").unwrap();
+
+        /*- Served stale until the watcher notices -*/
+        assert_eq!(cache.get(&path).map(|content| content.len()), Some(10));
+        thread::sleep(Duration::from_millis(200));
+        assert!(cache.get(&path).is_none());
+
+        fs::remove_dir_all(&dir).unwrap_or(());
+    }
+}
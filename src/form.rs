@@ -0,0 +1,278 @@
+/*- Imports -*/
+use percent_encoding::percent_decode;
+use crate::{ Request, Response, ResponseType };
+
+/// A decoded `application/x-www-form-urlencoded` body (or query string).
+/// Names can appear more than once, like for checkboxes, and the order is kept
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Form {
+    pub pairs:Vec<(String, String)>,
+}
+
+/// Limits for parsing form bodies
+#[derive(Debug, Clone, Copy)]
+pub struct FormLimits {
+    /// Larger bodies are refused with `413`
+    pub max_bytes:usize,
+    /// More fields than this are refused with `400`
+    pub max_fields:usize,
+}
+
+impl Default for FormLimits {
+    fn default() -> FormLimits {
+        FormLimits { max_bytes: 1024 * 1024, max_fields: 1000 }
+    }
+}
+
+/// The charsets form bodies can be decoded from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Charset {
+    Utf8,
+    /// Every byte is the code point of the same number. `Charset::parse` never gives
+    /// this, browsers send `iso-8859-1` and `us-ascii` forms as windows-1252
+    Latin1,
+    /// Latin1, except for 0x80 to 0x9F, which are printable characters like `€`
+    Windows1252,
+}
+
+/*- What windows-1252 has at 0x80 to 0x9F. The five bytes it leaves
+    undefined are kept as the control characters, like browsers do -*/
+const WINDOWS_1252:[char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+impl Charset {
+    /// Parse a charset label like `UTF-8` or `iso-8859-1`. Like in browsers, the
+    /// latin1 and ascii labels give `Windows1252`
+    pub fn parse(label:&str) -> Option<Charset> {
+        match label.trim().trim_matches('"').to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Charset::Utf8),
+            "iso-8859-1" | "latin1" | "l1" | "us-ascii" | "ascii" | "windows-1252" | "cp1252" => Some(Charset::Windows1252),
+            _ => None,
+        }
+    }
+
+    fn decode(&self, bytes:&[u8]) -> String {
+        match self {
+            Charset::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Charset::Latin1 => bytes.iter().map(|&byte| byte as char).collect(),
+            Charset::Windows1252 => bytes.iter().map(|&byte| match byte {
+                0x80..=0x9F => WINDOWS_1252[(byte - 0x80) as usize],
+                byte => byte as char,
+            }).collect(),
+        }
+    }
+}
+
+impl Form {
+
+    /// Decode `name=value&other=value+with+spaces`, percent-escapes are decoded from `charset`
+    pub fn parse(input:&str, charset:Charset) -> Form {
+        Form::parse_bytes(input.as_bytes(), charset)
+    }
+
+    /// Like `parse`, for a body as it was sent. Bytes that aren't
+    /// percent-escaped are decoded from `charset` too
+    pub fn parse_bytes(input:&[u8], charset:Charset) -> Form {
+        let decode = |part:&[u8]| {
+            let part:Vec<u8> = part.iter().map(|&byte| if byte == b'+' { b' ' } else { byte }).collect();
+            charset.decode(&percent_decode(&part).collect::<Vec<u8>>())
+        };
+
+        let pairs = input.split(|&byte| byte == b'&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.iter().position(|&byte| byte == b'=') {
+                Some(index) => (decode(&pair[..index]), decode(&pair[index + 1..])),
+                None => (decode(pair), String::new()),
+            })
+            .collect();
+
+        Form { pairs }
+    }
+
+    /// The first value of a field
+    pub fn get(&self, name:&str) -> Option<&str> {
+        self.pairs.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of a field, in order
+    pub fn get_all(&self, name:&str) -> Vec<&str> {
+        self.pairs.iter().filter(|(key, _)| key == name).map(|(_, value)| value.as_str()).collect()
+    }
+
+    pub fn contains(&self, name:&str) -> bool {
+        self.pairs.iter().any(|(key, _)| key == name)
+    }
+
+    pub fn len(&self) -> usize { self.pairs.len() }
+    pub fn is_empty(&self) -> bool { self.pairs.is_empty() }
+
+    /// Deserialize the fields into a struct. Fields like numbers and bools are parsed from their text
+    #[cfg(feature = "form")]
+    pub fn deserialize<T:serde::de::DeserializeOwned>(&self) -> Result<T, String> {
+        /*- serde_urlencoded only reads utf-8, so the already decoded pairs are encoded again -*/
+        let encoded = serde_urlencoded::to_string(&self.pairs).map_err(|error| error.to_string())?;
+        serde_urlencoded::from_str(&encoded).map_err(|error| error.to_string())
+    }
+}
+
+impl Request {
+
+    /// Parse a form body with the default `FormLimits`
+    /// # Examples
+    /// ```ignore
+    /// fn subscribe(request:&Request) -> Response {
+    ///     let form = match request.form() {
+    ///         Ok(form) => form,
+    ///         Err(response) => return response,
+    ///     };
+    ///     let topics = form.get_all("topic");
+    ///     ...
+    /// }
+    /// ```
+    pub fn form(&self) -> Result<Form, Response> {
+        self.form_with(FormLimits::default())
+    }
+
+    /// Parse a form body. Fails with a response to send back: `415` if the `Content-Type`
+    /// isn't a form or has an unknown charset, `413` or `400` if it's over the limits
+    pub fn form_with(&self, limits:FormLimits) -> Result<Form, Response> {
+        if self.headers.mime().as_deref() != Some("application/x-www-form-urlencoded") {
+            return Err(Response::error(415, &ResponseType::Text));
+        };
+
+        /*- Browsers percent-encode the bytes in the charset of the page, utf-8 if nothing says otherwise -*/
+        let charset = match self.headers.charset() {
+            Some(label) => match Charset::parse(label) {
+                Some(charset) => charset,
+                None => return Err(Response::error(415, &ResponseType::Text)),
+            },
+            None => Charset::Utf8,
+        };
+
+        /*- The bytes as they were sent, a lossy string would turn
+            anything that isn't utf-8 into U+FFFD before decoding -*/
+        let body = self.body_bytes();
+        if body.len() > limits.max_bytes {
+            return Err(Response::error(413, &ResponseType::Text));
+        };
+        if body.split(|&byte| byte == b'&').filter(|pair| !pair.is_empty()).count() > limits.max_fields {
+            return Err(Response::error(400, &ResponseType::Text));
+        };
+
+        Ok(Form::parse_bytes(body, charset))
+    }
+
+    /// Parse a form body into a struct, `400` if it doesn't fit
+    #[cfg(feature = "form")]
+    pub fn form_as<T:serde::de::DeserializeOwned>(&self) -> Result<T, Response> {
+        self.form()?.deserialize().map_err(|error| {
+            tracing::debug!(%error, "form doesn't fit");
+            Response::error(400, &ResponseType::Text)
+        })
+    }
+
+    /// The query string as a form
+    pub fn query_form(&self) -> Form {
+        Form::parse(&self.query, Charset::Utf8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plus_is_a_space() {
+        let form = Form::parse("name=John+Doe&q=a+%2B+b", Charset::Utf8);
+        assert_eq!(form.get("name"), Some("John Doe"));
+        /*- An escaped plus stays a plus -*/
+        assert_eq!(form.get("q"), Some("a + b"));
+    }
+
+    #[test]
+    fn percent_escapes() {
+        let form = Form::parse("city=K%C3%B8benhavn&sym=%26%3D%25&na%6De=x", Charset::Utf8);
+        assert_eq!(form.get("city"), Some("København"));
+        assert_eq!(form.get("sym"), Some("&=%"));
+        assert_eq!(form.get("name"), Some("x"));
+
+        /*- Latin-1 bodies have one byte per character -*/
+        let form = Form::parse("city=K%F8benhavn", Charset::Latin1);
+        assert_eq!(form.get("city"), Some("København"));
+    }
+
+    #[test]
+    fn invalid_escapes_are_kept() {
+        let form = Form::parse("a=100%&b=%zz&c=%4&d=%FF", Charset::Utf8);
+        assert_eq!(form.get("a"), Some("100%"));
+        assert_eq!(form.get("b"), Some("%zz"));
+        assert_eq!(form.get("c"), Some("%4"));
+        /*- Not utf-8, replaced instead of failing -*/
+        assert_eq!(form.get("d"), Some("\u{FFFD}"));
+    }
+
+    #[test]
+    fn repeated_keys() {
+        let form = Form::parse("tag=a&x=1&tag=b&&tag=&flag", Charset::Utf8);
+        assert_eq!(form.get("tag"), Some("a"));
+        assert_eq!(form.get_all("tag"), vec!["a", "b", ""]);
+        assert_eq!(form.get("flag"), Some(""));
+        assert!(form.contains("flag"));
+        assert!(!form.contains("missing"));
+        /*- Empty pairs (`&&`) are skipped, the order is kept -*/
+        assert_eq!(form.len(), 5);
+        assert_eq!(form.pairs[1], (String::from("x"), String::from("1")));
+    }
+
+    #[test]
+    fn windows_1252() {
+        let form = Form::parse("price=5%80&quote=%93hi%94&e=%E9", Charset::Windows1252);
+        assert_eq!(form.get("price"), Some("5€"));
+        assert_eq!(form.get("quote"), Some("“hi”"));
+        assert_eq!(form.get("e"), Some("é"));
+
+        /*- Undefined bytes stay control characters -*/
+        assert_eq!(Form::parse("x=%81", Charset::Windows1252).get("x"), Some("\u{81}"));
+
+        assert_eq!(Charset::parse("ISO-8859-1"), Some(Charset::Windows1252));
+        assert_eq!(Charset::parse("\"us-ascii\""), Some(Charset::Windows1252));
+        assert_eq!(Charset::parse("utf8"), Some(Charset::Utf8));
+        assert_eq!(Charset::parse("shift_jis"), None);
+    }
+
+    fn request(content_type:&str, body:&[u8]) -> Request {
+        let mut request = Request::parse(format!("POST /form HTTP/1.1\r\nContent-Type: {}\r\n\r\n", content_type), None);
+        request.body_bytes = Some(std::sync::Arc::new(body.to_vec()));
+        request
+    }
+
+    #[test]
+    fn bodies_are_decoded_from_the_raw_bytes() {
+        /*- `é` sent unescaped, as one latin1 byte -*/
+        let form = request("application/x-www-form-urlencoded; charset=windows-1252", b"name=Ren\xE9&price=\x805").form().unwrap();
+        assert_eq!(form.get("name"), Some("René"));
+        assert_eq!(form.get("price"), Some("€5"));
+
+        let form = request("application/x-www-form-urlencoded", "name=Ren\u{e9}".as_bytes()).form().unwrap();
+        assert_eq!(form.get("name"), Some("René"));
+    }
+
+    #[test]
+    fn requests_that_dont_fit_are_refused() {
+        let status = |request:Request, limits:FormLimits| request.form_with(limits).map(|_| 200).unwrap_or_else(|response| response.status);
+        let limits = FormLimits { max_bytes: 16, max_fields: 2 };
+
+        assert_eq!(status(request("application/json", b"a=1"), limits), 415);
+        assert_eq!(status(request("application/x-www-form-urlencoded; charset=shift_jis", b"a=1"), limits), 415);
+        assert_eq!(status(request("application/x-www-form-urlencoded", b"a=1&b=2"), limits), 200);
+        assert_eq!(status(request("application/x-www-form-urlencoded", b"a=1&b=2&c=3"), limits), 400);
+
+        /*- The size is the one on the wire. 16 bytes, which are 48 as a lossy string -*/
+        assert_eq!(status(request("application/x-www-form-urlencoded; charset=latin1", &[b'\xE9'; 16]), limits), 200);
+        assert_eq!(status(request("application/x-www-form-urlencoded", &[b'a'; 17]), limits), 413);
+    }
+}
@@ -0,0 +1,55 @@
+/*- Imports -*/
+use serde::{ de::DeserializeOwned, Serialize };
+use crate::{ Request, Response, ResponseType };
+use crate::access_log::json_escape;
+use crate::utils::status_message;
+
+impl Request {
+
+    /// Parse the body as json. Fails with a response to send back: `415` if
+    /// the `Content-Type` isn't json, and `400` if the body doesn't parse
+    /// # Examples
+    /// ```ignore
+    /// fn create_user(request:&Request) -> Response {
+    ///     let user:NewUser = match request.json() {
+    ///         Ok(user) => user,
+    ///         Err(response) => return response,
+    ///     };
+    ///     Response::json(201, &save(user))
+    /// }
+    /// ```
+    pub fn json<T:DeserializeOwned>(&self) -> Result<T, Response> {
+        if !self.headers.mime().map(|mime| is_json(&mime)).unwrap_or(false) {
+            return Err(json_error(415, "expected a json body"));
+        };
+
+        serde_json::from_slice(self.body_bytes()).map_err(|error| json_error(400, &error.to_string()))
+    }
+}
+
+impl Response {
+
+    /// A json response with any serializable value as the body
+    pub fn json<T:Serialize + ?Sized>(status:u16, value:&T) -> Response {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response::content(status, ResponseType::Json, body),
+            Err(error) => {
+                tracing::error!(%error, "couldn't serialize a json response");
+                json_error(500, "couldn't serialize the response")
+            },
+        }
+    }
+}
+
+/*- application/json, or anything like application/problem+json -*/
+fn is_json(mime:&str) -> bool {
+    mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+}
+
+/*- Like Response::error, but with what went wrong -*/
+fn json_error(status:u16, error:&str) -> Response {
+    Response::content(status, ResponseType::Json, format!(
+        "{{\"status\":{},\"message\":\"{}\",\"error\":\"{}\"}}",
+        status, status_message(status), json_escape(error)
+    ))
+}
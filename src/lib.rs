@@ -2,19 +2,47 @@
 //! Webserver is a simple way of serving static files / api endpoints.
 //!
 
-#![allow(dead_code, deprecated)]
+#![allow(dead_code, deprecated, clippy::needless_return)]
 
 /*- Imports -*/
 use std::net::TcpListener;
 use std::net::TcpStream;
 use std::io::prelude::*;
 use std::collections::HashMap;
-use utils::guess_response_type;
 use webhandler::ThreadHandler;
 use std::fs;
 use termcolor::Color;
 use std::path::Path;
+use std::fmt;
+use std::sync::Arc;
+use routes::join_path;
+use middleware::{ run_before, run_after };
+use scope::Scope;
+use compression::compress_response;
+use listing::listing_response;
+use percent_encoding::percent_decode_str;
+use utils::etag;
 pub use crate::utils::*;
+pub use crate::routes::*;
+pub use crate::request::Request;
+pub use crate::response::Response;
+pub use crate::middleware::{ Middleware, Before, After };
+pub use crate::scope::{ StackOptions, AuthFunction };
+pub use crate::access_log::{ AccessLog, AccessEntry, LogFormat, LogSink };
+pub use crate::mime::{ guess_mime, sniff_mime, with_charset, DEFAULT_MIME };
+pub use crate::compression::{ Compression, Encoding, negotiate, is_compressible, add_vary };
+pub use crate::cache::{ CacheRule, CacheControl };
+pub use crate::file_cache::{ FileCache, Invalidation };
+pub use crate::embed::{ EmbeddedDir, EmbeddedFile, embed_dir };
+pub use crate::error_pages::{ ErrorPages, ErrorPage, prefers_json };
+pub use crate::form::{ Form, FormLimits, Charset };
+pub use crate::headers::HeaderMap;
+pub use crate::cookies::{ Cookie, CookieJar, SameSite };
+pub use crate::limits::Limits;
+pub use crate::multipart::{ Multipart, MultipartLimits, MultipartError, Part, SavedPart, PartData, TempFile };
+#[cfg(feature = "secure-cookies")]
+pub use crate::secure_cookies::{ CookieKeys, SignedJar, PrivateJar };
+pub use crate::sessions::{ Sessions, Session, SessionData, SessionStore, MemoryStore, FileStore };
 
 /// The options that the user has before starting the server
 #[derive(Clone)]
@@ -27,6 +55,46 @@ pub struct ServerOptions {
     pub log_status:bool,
     pub on_connect:Option<fn(&String)>,
     pub statics:Statics,
+    /// Layers that every request goes through, see `Middleware`
+    pub middleware:Vec<Middleware>,
+    /// Log every request, see `AccessLog`
+    pub access_log:Option<AccessLog>,
+    /// Compress responses for clients that accept it, see `Compression`
+    pub compression:Option<Compression>,
+    /// More static directories, served at their own url prefixes. See `Mount`
+    pub mounts:Vec<Mount>,
+    /// Pages for error statuses, see `ErrorPages`
+    pub error_pages:ErrorPages,
+    /// Size limits for requests, see `Limits`
+    pub limits:Limits,
+    /// The secrets signed and private cookies are made with, see `CookieKeys`
+    #[cfg(feature = "secure-cookies")]
+    pub cookie_keys:Option<CookieKeys>,
+    /// Server side sessions, see `Sessions`
+    pub sessions:Option<Sessions>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> ServerOptions {
+        ServerOptions {
+            url: "127.0.0.1",
+            port: 8081,
+            numthreads: 10,
+            routes: Vec::new(),
+            log_status: true,
+            on_connect: None,
+            statics: Statics::default(),
+            middleware: Vec::new(),
+            access_log: None,
+            compression: None,
+            mounts: Vec::new(),
+            error_pages: ErrorPages::default(),
+            limits: Limits::default(),
+            #[cfg(feature = "secure-cookies")]
+            cookie_keys: None,
+            sessions: None,
+        }
+    }
 }
 
 /// Options related to serving static files
@@ -35,6 +103,100 @@ pub struct Statics {
     pub dir:&'static str,
     pub serve:bool,
     pub custom404:Option<&'static str>,
+    /// Extra (extension, mime type) pairs, checked before the built in ones
+    pub mime_types:Vec<(&'static str, &'static str)>,
+    /// Look at the content of files with unknown extensions to guess their type.
+    /// Otherwise they're served as `text/plain`
+    pub sniff:bool,
+    /// Serve `file.br` / `file.gz` / `file.zst` instead of `file` if they
+    /// exist and the client accepts that encoding. The first ones are preferred
+    pub precompressed:Vec<Encoding>,
+    /// Files served for requests to a directory, like `index.html`
+    pub index:Vec<&'static str>,
+    /// Redirect directories without a trailing slash (`/docs`) to `/docs/`
+    pub redirect_slash:bool,
+    /// Generate a listing for directories without an index file
+    pub listing:bool,
+    /// `Cache-Control` header value for everything served from the dir
+    /// that doesn't match any of the `cache_rules`
+    pub cache_control:Option<&'static str>,
+    /// `Cache-Control` values by path pattern, the first matching one is used
+    pub cache_rules:Vec<CacheRule>,
+    /// Keep small files in memory, instead of reading them on every request
+    pub file_cache:Option<FileCache>,
+    /// Serve files that were embedded into the binary instead of reading `dir`
+    pub embedded:Option<&'static EmbeddedDir>,
+    /// File served (with status 200) for GET requests that accept `text/html` and
+    /// don't match anything else, like `index.html` for client side routing
+    pub fallback:Option<&'static str>,
+    /// Request paths starting with these prefixes (like `/api`) never get the fallback
+    pub fallback_exclude:Vec<&'static str>,
+}
+
+impl Default for Statics {
+    fn default() -> Statics {
+        Statics { dir: "./static", serve: true, custom404: None, mime_types: Vec::new(), sniff: false, precompressed: Vec::new(),
+            index: Vec::new(), redirect_slash: false, listing: false, cache_control: None,
+            cache_rules: Vec::new(), file_cache: None, embedded: None,
+            fallback: None, fallback_exclude: Vec::new() }
+    }
+}
+
+/// A static directory served at a url prefix, like `/assets` -> `./dist`.
+/// Every mount has its own `Statics` options (index files, listing,
+/// 404 page, caching). `Statics::dir` is the directory to serve, and
+/// `Statics::serve` can be used to turn the mount off.
+///
+/// Requests are resolved in this order:
+/// 1. Mounts, the longest matching prefix first
+/// 2. `ServerOptions::statics` (if `serve` is true)
+/// 3. `ServerOptions::routes`
+/// 4. The 404 page of the mount the path is inside of, otherwise `ServerOptions::statics`'s
+/// # Examples
+/// ```ignore
+/// mounts: vec![
+///     Mount { prefix: "/assets", statics: Statics { dir: "./dist", cache_control: Some("max-age=31536000"), ..Default::default() } },
+///     Mount { prefix: "/media", statics: Statics { dir: "/var/media", listing: true, ..Default::default() } },
+/// ],
+/// ```
+#[derive(Clone)]
+pub struct Mount {
+    pub prefix:&'static str,
+    pub statics:Statics,
+}
+
+impl Mount {
+    /// The path relative to the mount, if the path is inside of it
+    pub fn strip<'a>(&self, path:&'a str) -> Option<&'a str> {
+        let prefix = self.prefix.trim_end_matches('/');
+        let rest = path.strip_prefix(prefix)?;
+
+        if rest.is_empty() || rest.starts_with('/') { Some(rest) } else { None }
+    }
+}
+
+impl Statics {
+    /// The `Cache-Control` value for a path inside of the static dir
+    pub fn cache_control_for(&self, path:&str) -> Option<&str> {
+        self.cache_rules.iter()
+            .find(|rule| rule.matches(path))
+            .map(|rule| rule.cache_control.as_str())
+            .or(self.cache_control)
+    }
+
+    /// The `Content-Type` of a file, with a charset for text types
+    pub fn content_type(&self, path:&str, content:&[u8]) -> String {
+        let extension = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("");
+
+        let mime = self.mime_types.iter()
+            .find(|(ext, _)| ext.eq_ignore_ascii_case(extension))
+            .map(|(_, mime)| *mime)
+            .or_else(|| guess_mime(path))
+            /*- Unknown files have always been served as text -*/
+            .unwrap_or_else(|| if self.sniff { sniff_mime(content) } else { "text/plain" });
+
+        with_charset(mime)
+    }
 }
 
 /*- Ok to explain the RouteRoot enum -> -*/
@@ -48,9 +210,13 @@ pub struct Statics {
 //    "subpath3",
 // ])
 // ("/otherPath", [ ... ])
+//
+// StackWith works exactly like Stack, but also takes options
+// which apply to every endpoint inside of it (and its sub-stacks).
 #[derive(Debug, Clone)]
 pub enum RouteRoot {
     Stack(&'static str, Vec<RouteRoot>),
+    StackWith(&'static str, StackOptions, Vec<RouteRoot>),
     Endpoint(&'static str, RouteValue),
 }
 
@@ -58,11 +224,41 @@ pub enum RouteRoot {
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Method { Get, Post, Put, None }
 
+impl fmt::Display for Method {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::None => "NONE",
+        })
+    }
+}
+
+impl Method {
+    /// Parse a method like `GET` (case-insensitive), unknown methods become `Method::None`
+    pub fn parse(method:&str) -> Method {
+        match method.to_ascii_lowercase().as_str() {
+            "get" => Method::Get,
+            "post" => Method::Post,
+            "put" => Method::Put,
+            _ => Method::None,
+        }
+    }
+}
+
+/*- The signature every function-endpoint has to follow -*/
+pub type RouteFunction = fn(TcpStream, String, HashMap<String, String>);
+
+/*- Handlers return their response instead of writing it, so middleware can modify it -*/
+pub type HandlerFunction = fn(&Request) -> Response;
+
 /*- Routes can either be a filepath, or a function -*/
 #[derive(Copy, Clone, Debug)]
 pub enum RouteValue {
     File(&'static str),
-    Function((Method, fn(TcpStream, String, HashMap<String, String>) -> ())),
+    Function((Method, RouteFunction)),
+    Handler((Method, HandlerFunction)),
     None
 }
 
@@ -70,29 +266,45 @@ pub enum RouteValue {
 /// # Arguments
 /// * `options` - The options that the user has before starting the server
 /// # Example
-/// ```
+/// ```ignore
 /// start(ServerOptions { ... })
 /// ```
 pub fn start(options:ServerOptions) {
 
     /*- The server will be active here -*/
     let server_url = format!("{}:{}",
-        options.url, options.port.to_string(),
+        options.url, options.port,
     );
 
     /*- If used in production, change unwrap to something that handles errors -*/
-    let server_listener:TcpListener = TcpListener::bind(&server_url).unwrap();
+    let server_listener:TcpListener = TcpListener::bind(&server_url).unwrap_or_else(|error| {
+        tracing::error!(address = %server_url, %error, "could not bind server");
+        panic!("Could not bind server to {}: {}", server_url, error);
+    });
 
-    /*- Log -*/
-    if options.log_status { log(Color::Rgb(255, 255, 0), format!("Server open on {}", &server_url).as_str()) };
+    /*- Log. Events always go through tracing, the colored output is optional -*/
+    tracing::info!(address = %server_url, "server open");
+    for entry in list_routes(&options.routes) {
+        tracing::debug!(route = %entry, "route");
+    };
+    if options.log_status {
+        log(Color::Rgb(255, 255, 0), format!("Server open on {}", &server_url).as_str());
+        log_routes(&options.routes);
+    };
 
     /*- Create a thread handler with 4 threads (users can join at same time) -*/
     let thread_handler = ThreadHandler::new(options.numthreads);
 
     /*- Start listening for connections -*/
     for stream_in in server_listener.incoming() {
-        /*- Stream in will return a result, unwrap -*/
-        let stream = stream_in.unwrap();
+        /*- Stream in will return a result, a failed connection shouldn't stop the server -*/
+        let stream = match stream_in {
+            Ok(stream) => stream,
+            Err(error) => {
+                tracing::warn!(%error, "failed to accept connection");
+                continue;
+            },
+        };
 
         /*- Get the options -*/
         let opt = options.clone();
@@ -106,26 +318,121 @@ pub fn start(options:ServerOptions) {
 
 /*- Handle all server connection -*/
 pub fn handle_connect(mut stream:TcpStream, options:ServerOptions) {
-    /*- Stream content buffer -*/
-    let mut data_buffer:[u8;1024]=[0;1024];
-
-    /*- Read the data and store it in the buffer -*/
-    stream.read(&mut data_buffer).unwrap_or(0);
+    /*- Read the data -*/
+    let (data, streamed) = match read_request(&mut stream, &options.limits) {
+        Ok(read) => read,
+        Err((data, status)) => return reject(&mut stream, &data, status, &options),
+    };
 
-    /*- Get the request -*/
-    let request = String::from_utf8_lossy(&data_buffer[..]);
+    /*- Get the request. Streamed bodies are left out, and read by the handler -*/
+    let (raw, rest) = match streamed {
+        Some((head_end, content_length)) => (
+            String::from_utf8_lossy(&data[..head_end + 4]).to_string(),
+            data[head_end + 4..].iter().take(content_length).copied().collect::<Vec<u8>>(),
+        ),
+        None => (String::from_utf8_lossy(&data).to_string(), Vec::new()),
+    };
 
     /*- On connect func -*/
-    if let Some(on_connect) = options.on_connect { on_connect(&request.to_string()) };
+    if let Some(on_connect) = options.on_connect { on_connect(&raw) };
+
+    let mut request = Request::parse(raw, stream.peer_addr().ok());
+    if streamed.is_none() {
+        if let Some(head_end) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            request.body_bytes = Some(Arc::new(data[head_end + 4..].to_vec()));
+        };
+    };
+    if let (Some((_, content_length)), Ok(body_stream)) = (streamed, stream.try_clone()) {
+        let remaining = content_length.saturating_sub(rest.len()) as u64;
+        request.stream_body(rest, body_stream, remaining);
+    };
 
     /*- Execute the path - either send a file or execute a function -*/
-    exec_path(request.to_string(), &mut stream, options);
+    exec_request(request, &mut stream, options);
+}
+
+/*- The bytes that were read, and (end of the head, Content-Length) if the body was left on the stream -*/
+type ReadRequest = (Vec<u8>, Option<(usize, usize)>);
+
+/*- Read the head of a request, and as much of the body as the Content-Length says.
+    Multipart bodies are left on the stream, then the end of the head and the
+    Content-Length are returned too. Requests over the limits give what was read,
+    and the status to answer with -*/
+fn read_request(stream:&mut TcpStream, limits:&Limits) -> Result<ReadRequest, (Vec<u8>, u16)> {
+    let mut data:Vec<u8> = Vec::new();
+    let mut buffer:[u8;4096] = [0;4096];
+
+    /*- The head first -*/
+    let head_end = loop {
+        if let Some(head_end) = data.windows(4).position(|window| window == b"\r\n\r\n") { break head_end; };
+        if data.len() > limits.max_header_size { return Err((data, 431)); };
+
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => return Ok((data, None)),
+            Ok(read) => data.extend_from_slice(&buffer[..read]),
+        };
+    };
+
+    let headers = HeaderMap::parse(&String::from_utf8_lossy(&data[..head_end]));
+    if head_end > limits.max_header_size || headers.len() > limits.max_headers {
+        return Err((data, 431));
+    };
+
+    /*- Chunked bodies aren't decoded, so their chunks would be left on the stream and read
+        as the next request. With a Content-Length too, that's how requests are smuggled,
+        and so are two Content-Lengths that don't agree -*/
+    if headers.contains("Transfer-Encoding") {
+        return Err((data, if headers.contains("Content-Length") { 400 } else { 501 }));
+    };
+    let lengths = headers.get_all("Content-Length");
+    if lengths.iter().any(|length| length.trim() != lengths[0].trim()) {
+        return Err((data, 400));
+    };
+
+    let content_length:usize = match (headers.get("Content-Length"), headers.content_length()) {
+        (Some(_), Some(length)) => length as usize,
+        (Some(_), None) => return Err((data, 400)),
+        (None, _) => 0,
+    };
+
+    let multipart = headers.mime().map(|mime| mime.starts_with("multipart/")).unwrap_or(false);
+    let max = if multipart { limits.max_multipart_size } else { limits.max_body_size };
+    if content_length > max { return Err((data, 413)); };
+    if multipart { return Ok((data, Some((head_end, content_length)))); };
+
+    /*- Then the body -*/
+    let end = head_end + 4 + content_length;
+    while data.len() < end {
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => data.extend_from_slice(&buffer[..read]),
+        };
+    };
+    data.truncate(end);
+
+    Ok((data, None))
+}
+
+/*- Answer a request that is over the limits (or can't be read), without routing it -*/
+fn reject(stream:&mut TcpStream, data:&[u8], status:u16, options:&ServerOptions) {
+    let request = Request::parse(String::from_utf8_lossy(data).to_string(), stream.peer_addr().ok());
+    let span = tracing::info_span!("request", method = %request.method, path = %request.path);
+    let _enter = span.enter();
+
+    /*- The rest of the request is never read, so the connection can't be reused -*/
+    let response = Response::error(status, &ResponseType::Text).header("Connection", "close");
+    let sent = finish(stream, &request, options, Some(response), &[]);
+    tracing::warn!(status, "request refused before routing");
+
+    if let Some(access_log) = &options.access_log {
+        access_log.write(&AccessEntry::new(&request, sent));
+    };
 }
 
 /*- Remove the trailing slash from a string -*/
 pub fn trail(path:&str) -> String {
     /*- If the path ends with a slash, remove it -*/
-    if path.ends_with("/") {
+    if path.ends_with('/') {
         return path[0..path.len()-1].to_string();
     }
 
@@ -134,46 +441,253 @@ pub fn trail(path:&str) -> String {
 }
 
 /*- A way of getting the URL route, or a return function -*/
-pub fn exec_path(request:String, stream:&mut TcpStream, options:ServerOptions) -> () {
-    /*- Get the path from the request -*/
-    let path = request.split("\n").nth(0).unwrap();
-    let path = path.split(" ").nth(1).unwrap();
+pub fn exec_path(request:String, stream:&mut TcpStream, options:ServerOptions) {
+    /*- Get the method and path from the request -*/
+    let request = Request::parse(request, stream.peer_addr().ok());
+    exec_request(request, stream, options);
+}
+
+/*- Route a parsed request, and log it -*/
+fn exec_request(mut request:Request, stream:&mut TcpStream, options:ServerOptions) {
+
+    /*- Everything logged while handling the request is inside of this span -*/
+    let span = tracing::info_span!("request", method = %request.method, path = %request.path);
+    let _enter = span.enter();
+
+    #[cfg(feature = "secure-cookies")]
+    { request.cookie_keys = options.cookie_keys.clone(); };
+    if let Some(sessions) = &options.sessions {
+        let cookie = request.cookies().get(&sessions.cookie.name).map(String::from);
+        request.session = Some(Session::new(sessions.clone(), cookie));
+    };
+
+    /*- Respond, and log what was sent -*/
+    let sent = route_request(&mut request, stream, &options);
+    let duration_ms = request.received.elapsed().as_secs_f64() * 1000.0;
+    match sent {
+        Some((status, bytes)) if status >= 500 => tracing::error!(status, bytes, duration_ms, "request finished"),
+        Some((status, bytes)) => tracing::info!(status, bytes, duration_ms, "request finished"),
+        None => tracing::info!(duration_ms, "request finished"),
+    };
+
+    if let Some(access_log) = &options.access_log {
+        access_log.write(&AccessEntry::new(&request, sent));
+    };
+}
+
+/*- Find and run whatever the request should get. Returns the
+    status and body size of the response, if we sent one -*/
+fn route_request(request:&mut Request, stream:&mut TcpStream, options:&ServerOptions) -> Option<(u16, usize)> {
+    /*- Server wide middleware runs before anything else, even static files -*/
+    let mut layers:Vec<Middleware> = options.middleware.clone();
+    if let Err((ran, response)) = run_before(&layers, request) {
+        return finish(stream, request, options, Some(response), &layers[..ran]);
+    };
 
-    /*- First check if user wants to serve all static files -*/
-    if options.statics.serve {
-        if send_file(stream, path, options.statics.dir) == true { return; }
+    /*- First check the mounts, and if user wants to serve all static files -*/
+    if let Some(response) = mounted_response(request, options) {
+        return finish(stream, request, options, Some(response), &layers);
     };
 
     /*- Get the users prefered 404 file -*/
     let custom_404 = options.statics.custom404.unwrap_or("404.html");
 
-    /*- See if the method matches the required one -*/
-    let requested_method:&str = &request.split(" ").nth(0).unwrap_or(&"").to_ascii_lowercase();
-    let requested_method:Method = match requested_method {
-        "get" => Method::Get,
-        "post" => Method::Post,
-        "put" => Method::Put,
-        _ => {
-            send_file(stream, custom_404, &options.statics.dir);
-            Method::None
-        }
+    /*- Iterate over all of them -*/
+    let value = iterate_routes(&options.routes, &request.path, 0u8, "", options, request.method);
+    request.params = value.params;
+
+    /*- The options of the stacks that the endpoint is inside of -*/
+    let scope = Scope::resolve(&value.scopes);
+    let server_layers = layers.len();
+
+    /*- Check the stacks requirements before running anything inside of them -*/
+    if !scope.auth.iter().all(|auth| auth(request)) {
+        return finish(stream, request, options, Some(error_response(401, &scope, options)), &layers);
+    };
+    if scope.max_header_size.map(|max| request.head().len() > max).unwrap_or(false)
+    || scope.max_headers.map(|max| request.headers.len() > max).unwrap_or(false) {
+        return finish(stream, request, options, Some(error_response(431, &scope, options)), &layers);
+    };
+    if let Some(max_body) = scope.max_body {
+        if request.headers.content_length().unwrap_or(0) > max_body as u64 {
+            return finish(stream, request, options, Some(error_response(413, &scope, options)), &layers);
+        };
     };
 
-    /*- Iterate over all of them -*/
-    let value = iterate_routes(&options.routes, path, 0u8, "", &options, requested_method);
+    /*- Middleware from the stacks -*/
+    layers.extend(scope.middleware.iter().copied());
+    if let Err((ran, response)) = run_before(&layers[server_layers..], request) {
+        return finish(stream, request, options, Some(response), &layers[..server_layers + ran]);
+    };
 
     /*- See if the value is either a function or a file -*/
-    match value.value {
-        RouteValue::File(file_path) => send_file(stream, file_path, &options.statics.dir),
-        RouteValue::Function((_, func)) => return func(stream.try_clone().unwrap(), request, value.params),
-        RouteValue::None => send_file(stream, custom_404, &options.statics.dir),
+    let response = match value.value {
+        RouteValue::File(file_path) => static_response(request, file_path, &options.statics)
+            .map(|response| with_cache_control(response, &options.statics, file_path)),
+        RouteValue::Handler((_, func)) => Some(func(request)),
+        RouteValue::Function((_, func)) => {
+            func(stream.try_clone().unwrap(), request.raw.clone(), request.params.clone());
+            return None;
+        },
+        RouteValue::None => match (scope.error_page, &scope.content_type) {
+            (None, None) => fallback_response(request, options)
+                .or_else(|| mount_404(request, options))
+                /*- A registered 404 page replaces the built-in one in finish -*/
+                .or_else(|| match options.error_pages.get(404) {
+                    Some(_) => None,
+                    None => page_response(404, custom_404, &options.statics),
+                })
+                .or_else(|| Some(error_response(404, &scope, options))),
+            _ => Some(error_response(404, &scope, options)),
+        },
     };
+
+    /*- Fill in the stacks default content type -*/
+    let response = response.map(|mut response| {
+        if let Some(content_type) = &scope.content_type {
+            if response.get_header("Content-Type").is_none() {
+                response.set_header("Content-Type", content_type.content_type());
+            };
+        };
+        response
+    });
+
+    finish(stream, request, options, response, &layers)
+}
+
+/*- Let the middleware modify the response, compress it and then send it -*/
+fn finish(stream:&mut TcpStream, request:&Request, options:&ServerOptions, response:Option<Response>, layers:&[Middleware]) -> Option<(u16, usize)> {
+    let mut response = response?;
+
+    /*- Errors without a body of their own get their error page -*/
+    if response.is_plain_error() {
+        if let Some(mut page) = options.error_pages.respond(request, response.status, &options.statics) {
+            for (key, value) in response.headers.iter() {
+                if page.get_header(key).is_none() { page.set_header(key, value); };
+            };
+            response = page;
+        };
+    };
+
+    run_after(layers, request, &mut response);
+    if let Some(session) = &request.session {
+        session.commit(&mut response);
+    };
+    if let Some(compression) = &options.compression {
+        compress_response(compression, request, &mut response);
+    };
+    response.write_to(stream);
+
+    Some((response.status, response.body.len()))
+}
+
+/*- The mounts (longest prefix first), and then the root static dir -*/
+fn mounted_response(request:&Request, options:&ServerOptions) -> Option<Response> {
+    let path = percent_decode_str(&request.path).decode_utf8_lossy().to_string();
+
+    for mount in sorted_mounts(&options.mounts) {
+        if !mount.statics.serve { continue; };
+        let rest = match mount.strip(&path) { Some(rest) => rest, None => continue };
+        if let Some(response) = static_response(request, rest, &mount.statics) {
+            return Some(with_cache_control(response, &mount.statics, rest));
+        };
+    };
+
+    if !options.statics.serve { return None; };
+    static_response(request, &path, &options.statics)
+        .map(|response| with_cache_control(response, &options.statics, &path))
+}
+
+/*- The 404 page of the (most specific) mount that the path is inside of -*/
+fn mount_404(request:&Request, options:&ServerOptions) -> Option<Response> {
+    let path = percent_decode_str(&request.path).decode_utf8_lossy().to_string();
+
+    sorted_mounts(&options.mounts).into_iter()
+        .find(|mount| mount.statics.serve && mount.statics.custom404.is_some() && mount.strip(&path).is_some())
+        .and_then(|mount| page_response(404, mount.statics.custom404?, &mount.statics))
+}
+
+/*- The fallback file of the mount the path is inside of, otherwise of the root statics -*/
+fn fallback_response(request:&Request, options:&ServerOptions) -> Option<Response> {
+    /*- Only page loads, not api calls, scripts or images. Browsers send text/html explicitly -*/
+    if request.method != Method::Get || !request.header("Accept").unwrap_or("").contains("text/html") {
+        return None;
+    };
+    let path = percent_decode_str(&request.path).decode_utf8_lossy().to_string();
+
+    let statics = match sorted_mounts(&options.mounts).into_iter().find(|mount| mount.statics.serve && mount.strip(&path).is_some()) {
+        Some(mount) => &mount.statics,
+        None if options.statics.serve => &options.statics,
+        None => return None,
+    };
+    let fallback = statics.fallback?;
+    if statics.fallback_exclude.iter().any(|prefix| prefix_matches(prefix, &path)) { return None; };
+
+    static_response(request, fallback, statics)
+        .map(|response| with_cache_control(response, statics, fallback))
+}
+
+/*- Longest prefix first, otherwise in the order they were declared -*/
+fn sorted_mounts(mounts:&[Mount]) -> Vec<&Mount> {
+    let mut sorted:Vec<&Mount> = mounts.iter().collect();
+    sorted.sort_by_key(|mount| std::cmp::Reverse(mount.prefix.trim_end_matches('/').len()));
+    sorted
+}
+
+/*- Add the statics Cache-Control header for the path, unless the response has one -*/
+fn with_cache_control(mut response:Response, statics:&Statics, path:&str) -> Response {
+    if !(200..300).contains(&response.status) && response.status != 304 { return response; };
+
+    /*- Directories are cached like their index file would be -*/
+    let path = match (path.ends_with('/') || path.is_empty(), statics.index.first()) {
+        (true, Some(index)) => format!("{}/{}", path.trim_end_matches('/'), index),
+        _ => path.to_string(),
+    };
+
+    if let Some(cache_control) = statics.cache_control_for(&path) {
+        if response.get_header("Cache-Control").is_none() {
+            response.set_header("Cache-Control", cache_control);
+        };
+    };
+    response
+}
+
+/*- An error inside of a stack, using the stacks error page or content type -*/
+fn error_response(status:u16, scope:&Scope, options:&ServerOptions) -> Response {
+    if let Some(response) = scope.error_page.and_then(|page| page_response(status, page, &options.statics)) {
+        return response;
+    };
+
+    Response::error(status, scope.content_type.as_ref().unwrap_or(&ResponseType::Text))
+}
+
+/*- An error page from the static dir (or the embedded one), sent with the real status -*/
+fn page_response(status:u16, page:&str, statics:&Statics) -> Option<Response> {
+    let mut response = match statics.embedded {
+        Some(embedded) => {
+            let file = embedded.get(page)?;
+            Response::content(status, ResponseType::Custom(statics.content_type(file.path, file.content)), file.content)
+        },
+        None => file_response(page, statics)?,
+    };
+
+    response.status = status;
+    Some(response)
+}
+
+/*- Check if a stack path (like /api/:version) is the start of the requested path -*/
+fn prefix_matches(prefix:&str, input_path:&str) -> bool {
+    let prefix_iter = remove_empty(prefix.split('/').collect::<Vec<&str>>());
+    let input_iter  = remove_empty(input_path.split('/').collect::<Vec<&str>>());
+
+    prefix_iter.len() <= input_iter.len()
+    && prefix_iter.iter().zip(input_iter.iter()).all(|(p, i)| p.starts_with(':') || p == i)
 }
 
 fn remove_empty(vec:Vec<&str>) -> Vec<&str> {
     let mut new_vec = Vec::new();
     for item in vec {
-        if item.len() > 0 {
+        if !item.is_empty() {
             new_vec.push(item);
         }
     }
@@ -185,11 +699,15 @@ fn remove_empty(vec:Vec<&str>) -> Vec<&str> {
 pub struct RoutesReturn {
     value:RouteValue,
     params:HashMap<String, String>,
+    /*- Options of the stacks the value was found in, outermost first. If
+        nothing was found, it's the deepest stack whose path matched -*/
+    scopes:Vec<StackOptions>,
 }
 
 /*- Iterate over all of the routes to find the path's value -*/
+#[allow(clippy::only_used_in_recursion)]
 pub fn iterate_routes(
-    routes:&Vec<RouteRoot>,
+    routes:&[RouteRoot],
     input_path:&str,
     index:u8,
     path_iter:&str,
@@ -198,7 +716,7 @@ pub fn iterate_routes(
 ) -> RoutesReturn {
 
     /*- What to finnaly return -*/
-    let mut return_value:RoutesReturn = RoutesReturn { value:RouteValue::None, params:HashMap::new() };
+    let mut return_value:RoutesReturn = RoutesReturn { value:RouteValue::None, params:HashMap::new(), scopes:Vec::new() };
 
     /*- Iterate -*/
     'main: for route in routes.iter() {
@@ -206,32 +724,48 @@ pub fn iterate_routes(
         match route {
             RouteRoot::Stack(path,routes) => {
                 let possible_route = iterate_routes(
-                    &routes.clone(),
+                    routes,
                     input_path,
                     index+1,
                     (path_iter.to_string().clone() + *path).as_str(),
-                    &options,
+                    options,
                     requested_method
                 );
                 
                 /*- If the route is a file, return it -*/
-                match possible_route.value {
-                    RouteValue::File(file_path) => return_value          = RoutesReturn { value:RouteValue::File(file_path),            params:possible_route.params },
-                    RouteValue::Function((method, func)) => return_value = RoutesReturn { value:RouteValue::Function((method, func)),   params:possible_route.params },
-                    RouteValue::None => (),
+                if !matches!(possible_route.value, RouteValue::None)
+                || (matches!(return_value.value, RouteValue::None) && possible_route.scopes.len() > return_value.scopes.len()) {
+                    return_value = possible_route;
+                };
+            },
+            RouteRoot::StackWith(path, stack_options, routes) => {
+                let mut possible_route = iterate_routes(
+                    routes,
+                    input_path,
+                    index+1,
+                    (path_iter.to_string().clone() + *path).as_str(),
+                    options,
+                    requested_method
+                );
+
+                /*- Same as Stack, but the stacks options applies to whatever was found.
+                    If nothing was, they still apply to the error if the path is inside of the stack -*/
+                let full_path = path_iter.to_string() + *path;
+                let found = !matches!(possible_route.value, RouteValue::None);
+                let inside = matches!(return_value.value, RouteValue::None)
+                    && possible_route.scopes.len() >= return_value.scopes.len()
+                    && prefix_matches(&full_path, input_path);
+
+                if found || inside {
+                    possible_route.scopes.insert(0, stack_options.clone());
+                    return_value = possible_route;
                 };
             },
             RouteRoot::Endpoint(enpoint_name, path) => {
 
                 /*- The full path to differentiate -*/
-                let full_path:String;
-
                 /*- Fixing som e problems with trailing slashes to avoid double slashes ("//") -*/
-                if !path_iter.ends_with("/") {
-                    full_path = path_iter.to_string() + "/" + enpoint_name;
-                } else {
-                    full_path = path_iter.to_string() + enpoint_name;
-                };
+                let full_path:String = join_path(path_iter, enpoint_name);
 
                 /*- The map will contain the uri-variables (params). Like when connecting to test/:id -*/
                 let mut map:HashMap<String, String> = HashMap::new();
@@ -241,15 +775,14 @@ pub fn iterate_routes(
 
                 /*- Add all params to the map -*/
                 for (index, param) in full_iter.clone().iter().enumerate() {
-                    if param.starts_with(":") {
+                    if let Some(name) = param.strip_prefix(':') {
 
                         /*- Find the index of param inside of the full_iter -*/
                         let remove_index:usize = full_iter.iter().position(|&x| &x == param).unwrap();
 
                         /*- Insert it into the map -*/
-                        map.insert(param[1..].to_string(), input_iter
-                                                                    .iter()
-                                                                    .nth(remove_index)
+                        map.insert(name.to_string(), input_iter
+                                                                    .get(remove_index)
                                                                     .unwrap_or(&"null")
                                                                     .to_string());
 
@@ -270,12 +803,12 @@ pub fn iterate_routes(
                     RouteValue::File(file_path) => {
                         /*- Check if the path matches the one inputted -*/
                         if full_iter == input_iter {
-                            return_value = RoutesReturn { value: RouteValue::File(file_path), params: map };
+                            return_value = RoutesReturn { value: RouteValue::File(file_path), params: map, scopes: Vec::new() };
 
                             break 'main;
                         }
                     },
-                    RouteValue::Function((required_method, func)) => {
+                    RouteValue::Function((required_method, _)) | RouteValue::Handler((required_method, _)) => {
                         /*- Check if the path matches the one inputted - again... -*/
                         if full_iter == input_iter {
 
@@ -286,20 +819,15 @@ pub fn iterate_routes(
                             // are any /api/upload-image paths that requires GET-methods, if it wasn't found,
                             // we'll send an 404 page.
                             if requested_method == *required_method {
-                                return_value = RoutesReturn { value: RouteValue::Function((*required_method, *func)), params: map };
+                                return_value = RoutesReturn { value: *path, params: map, scopes: Vec::new() };
 
                                 /*- We only break the search loop when we actually find something to return. -*/
                                 break 'main;
                             }
                         }
                     },
-                    RouteValue::None => {
-                        if let Some(c) = options.statics.custom404 {
-                            return_value = RoutesReturn { value: RouteValue::File(c), params: map };
-                        }else {
-                            return_value = RoutesReturn { value: RouteValue::File("404.html"), params: map };
-                        };
-                    },
+                    /*- Nothing to serve, the 404 page is sent once no other route matched -*/
+                    RouteValue::None => (),
                 };
             },
         };
@@ -311,32 +839,207 @@ pub fn iterate_routes(
 
 /*- Send a file with its content -*/
 pub fn send_file(stream:&mut TcpStream, path:&str, static_file_path:&str) -> bool {
+    match safe_join(static_file_path, path).and_then(|full_path| read_file(&full_path, &Statics::default())) {
+        Some(response) => {
+            response.write_to(stream);
+            true
+        },
+        None => false,
+    }
+}
 
-    /*- Get the FULL file path -*/
-    let full_path = format!("{}/{}", static_file_path, path);
-
-    /*- Check if the file exists -*/
-    if !Path::new(&full_path).is_file() { return false; };
-
-    /*- Get the file contents -*/
-    let file_content:&str = &fs::read_to_string(
-        &full_path
-    )
-    /*- We obviously don't want to panic whilst the server is
-        running (will cause server to shut down), so we'll just
-        send a file-not-found message if the file wasn't found -*/
-    .unwrap_or(
-        format!("File not found: \"{full_path}\"")
-    );
+/*- Get a response with a file's content, or None if the file doesn't exist -*/
+pub fn file_response(path:&str, statics:&Statics) -> Option<Response> {
+    read_file(&safe_join(statics.dir, path)?, statics)
+}
+
+/*- Like file_response, but uses a precompressed version of the file if the client
+    accepts it, handles directories (index files, redirects and listings) and ETags -*/
+fn static_response(request:&Request, path:&str, statics:&Statics) -> Option<Response> {
+    let response = match statics.embedded {
+        Some(embedded) => embedded_response(request, path, embedded, statics)?,
+        None => disk_response(request, path, statics)?,
+    };
+
+    Some(with_etag(request, response))
+}
+
+/*- Serve a path from the static dir -*/
+fn disk_response(request:&Request, path:&str, statics:&Statics) -> Option<Response> {
+    let full_path = safe_join(statics.dir, path)?;
+
+    /*- Cached files are known to be files, so the directory checks can be skipped -*/
+    if statics.precompressed.is_empty() {
+        if let Some(content) = statics.file_cache.as_ref().and_then(|cache| cache.get(&full_path)) {
+            let content_type = statics.content_type(&full_path, &content);
+            return Some(Response::content(200u16, ResponseType::Custom(content_type), content.to_vec()));
+        };
+    };
+    if Path::new(&full_path).is_dir() {
+        return directory_response(request, path, statics);
+    };
+    if statics.precompressed.is_empty() || !Path::new(&full_path).is_file() {
+        return file_response(path, statics);
+    };
+
+    /*- Only negotiate between the encodings that have a file -*/
+    let available:Vec<Encoding> = statics.precompressed.iter()
+        .filter(|encoding| Path::new(&format!("{}.{}", full_path, encoding.extension())).is_file())
+        .copied()
+        .collect();
+    if available.is_empty() { return file_response(path, statics); };
+
+    let mut response = match negotiate(request.header("Accept-Encoding").unwrap_or(""), &available) {
+        Some(encoding) => {
+            let compressed = read_bytes(&format!("{}.{}", full_path, encoding.extension()), statics)?;
+
+            /*- The type comes from the original file, not the .br / .gz one -*/
+            let content_type = statics.content_type(&full_path, &[]);
+            Response::content(200u16, ResponseType::Custom(content_type), compressed)
+                .header("Content-Encoding", encoding.as_str())
+        },
+        None => file_response(path, statics)?,
+    };
+
+    add_vary(&mut response, "Accept-Encoding");
+    Some(response)
+}
+
+/*- Serve a path from a dir that was embedded into the binary -*/
+fn embedded_response(request:&Request, path:&str, embedded:&EmbeddedDir, statics:&Statics) -> Option<Response> {
+    if path.split(['/', '\\']).any(|segment| segment == "..") { return None; };
+
+    let file = match embedded.get(path) {
+        Some(file) => file,
+        None if embedded.is_dir(path) => return directory_response(request, path, statics),
+        None => return None,
+    };
+
+    /*- Precompressed versions are embedded next to the file, like in a dir on disk -*/
+    let available:Vec<Encoding> = statics.precompressed.iter()
+        .filter(|encoding| embedded.get(&format!("{}.{}", file.path, encoding.extension())).is_some())
+        .copied()
+        .collect();
+    let encoding = negotiate(request.header("Accept-Encoding").unwrap_or(""), &available);
+    let variant = match encoding {
+        Some(encoding) => embedded.get(&format!("{}.{}", file.path, encoding.extension()))?,
+        None => file,
+    };
+
+    let content_type = statics.content_type(file.path, file.content);
+    let mut response = Response::content(200u16, ResponseType::Custom(content_type), variant.content)
+        .header("ETag", variant.etag);
+    if let Some(encoding) = encoding {
+        response.set_header("Content-Encoding", encoding.as_str());
+    };
+    if !available.is_empty() {
+        add_vary(&mut response, "Accept-Encoding");
+    };
+
+    Some(response)
+}
+
+/*- Add an ETag to a static file response, and answer 304 if the client has that version already -*/
+fn with_etag(request:&Request, mut response:Response) -> Response {
+    if response.status != 200 { return response; };
+
+    let etag = match response.get_header("ETag") {
+        Some(etag) => etag.to_string(),
+        None => {
+            let etag = etag(&response.body);
+            response.set_header("ETag", &etag);
+            etag
+        },
+    };
+
+    let fresh = request.header("If-None-Match").map(|tags| {
+        tags.trim() == "*" || tags.split(',').any(|tag| tag.trim().trim_start_matches("W/") == etag)
+    }).unwrap_or(false);
+    if fresh {
+        response.status = 304;
+        response.body = Vec::new();
+    };
+
+    response
+}
+
+/*- If a path is a file in the static dir (or the embedded one) -*/
+fn is_static_file(path:&str, statics:&Statics) -> bool {
+    match statics.embedded {
+        Some(embedded) => embedded.get(path).is_some(),
+        None => safe_join(statics.dir, path).map(|full_path| Path::new(&full_path).is_file()).unwrap_or(false),
+    }
+}
+
+/*- Requests to a directory -*/
+fn directory_response(request:&Request, path:&str, statics:&Statics) -> Option<Response> {
+    if statics.index.is_empty() && !statics.listing { return None; };
+
+    /*- Relative links in index files and listings only work with a trailing slash -*/
+    if !request.path.ends_with('/') {
+        if !statics.redirect_slash { return None; };
+
+        /*- `//host` (or `/\host`) would be a protocol-relative redirect to another site -*/
+        let path = format!("/{}", request.path.trim_start_matches(['/', '\\']));
+        let location = match request.query.as_str() {
+            "" => format!("{}/", path),
+            query => format!("{}/?{}", path, query),
+        };
+        return Some(Response::new(301).header("Location", &location));
+    };
+
+    /*- The first index file that exists -*/
+    for index in statics.index.iter() {
+        let index_path = format!("{}/{}", path.trim_end_matches('/'), index);
+        if is_static_file(&index_path, statics) {
+            return static_response(request, &index_path, statics);
+        };
+    };
+
+    /*- Embedded dirs can't be listed -*/
+    match (statics.listing, statics.embedded) {
+        (true, None) => listing_response(request, &safe_join(statics.dir, path)?),
+        _ => None,
+    }
+}
+
+/*- Join the static dir and a requested path, refusing paths that would escape the dir.
+    A leading slash stays inside of the dir, but drive letters (`C:`) would not on windows -*/
+fn safe_join(dir:&str, path:&str) -> Option<String> {
+    let escapes = |segment:&str| segment == ".."
+        || (segment.len() == 2 && segment.ends_with(':') && segment.starts_with(|c:char| c.is_ascii_alphabetic()));
+    if path.contains('\0') || path.split(['/', '\\']).any(escapes) { return None; };
+
+    Some(format!("{}/{}", dir, path))
+}
+
+/*- The statics are only used for figuring out the content type here -*/
+fn read_file(full_path:&str, statics:&Statics) -> Option<Response> {
+
+    /*- Get the file contents (from the cache, if there is one). We obviously
+        don't want to panic whilst the server is running (will cause server to
+        shut down), so we'll just send a file-not-found message if it can't be read -*/
+    let file_content:Vec<u8> = match read_bytes(full_path, statics) {
+        Some(content) => content,
+        None if !Path::new(full_path).is_file() => return None,
+        None => format!("File not found: \"{full_path}\"").into_bytes(),
+    };
 
     /*- Get the response type, if it's HTML, Text, Json and so on -*/
-    let response_type:ResponseType = guess_response_type(&full_path);
+    let content_type = statics.content_type(full_path, &file_content);
 
     /*- Respond -*/
-    respond(stream, 200u16, Some(response_type), Some(&file_content));
+    Some(Response::content(200u16, ResponseType::Custom(content_type), file_content))
+}
+
 
-    /*- Return success -*/
-    true
+/*- Read a file through the statics file cache, if there is one -*/
+fn read_bytes(full_path:&str, statics:&Statics) -> Option<Vec<u8>> {
+    let cache = match &statics.file_cache {
+        Some(cache) => cache,
+        None => return fs::read(full_path).ok(),
+    };
+    cache.read(full_path).ok().map(|content| content.to_vec())
 }
 
 
@@ -345,4 +1048,151 @@ pub fn send_file(stream:&mut TcpStream, path:&str, static_file_path:&str) -> boo
 pub(crate) mod utils;
 
 /*- Handle requests concurrently -*/
-pub(crate) mod webhandler;
\ No newline at end of file
+pub(crate) mod webhandler;
+
+/*- Flattening and listing the route tree -*/
+pub(crate) mod routes;
+
+/*- The parsed request, and the response before it's sent -*/
+pub(crate) mod request;
+pub(crate) mod response;
+
+/*- Layers around the route handlers -*/
+pub(crate) mod middleware;
+
+/*- Options that stacks pass down to their endpoints -*/
+pub(crate) mod scope;
+
+/*- Per-request access logs -*/
+pub(crate) mod access_log;
+
+/*- Figuring out the content type of files -*/
+pub(crate) mod mime;
+
+/*- Gzip, brotli and zstd response bodies -*/
+pub(crate) mod compression;
+
+/*- Generated directory listings -*/
+pub(crate) mod listing;
+
+/*- Cache-Control policies -*/
+pub(crate) mod cache;
+
+/*- Static files kept in memory -*/
+pub(crate) mod file_cache;
+
+/*- Static files compiled into the binary -*/
+pub(crate) mod embed;
+
+/*- Pages for error statuses -*/
+pub(crate) mod error_pages;
+
+/*- Url-encoded form bodies -*/
+pub(crate) mod form;
+
+/*- Request headers -*/
+pub(crate) mod headers;
+
+/*- Cookies, and Set-Cookie headers -*/
+pub(crate) mod cookies;
+
+/*- Size limits for requests -*/
+pub(crate) mod limits;
+
+/*- Multipart bodies and file uploads -*/
+pub(crate) mod multipart;
+
+/*- Typed json bodies, with serde -*/
+#[cfg(feature = "json")]
+pub(crate) mod json;
+
+/*- Signed and encrypted cookies -*/
+#[cfg(feature = "secure-cookies")]
+pub(crate) mod secure_cookies;
+
+/*- Server side sessions -*/
+pub(crate) mod sessions;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_join_stays_inside_of_the_dir() {
+        assert_eq!(safe_join("static", "/css/style.css").as_deref(), Some("static//css/style.css"));
+        assert_eq!(safe_join("static", "/a..b/..c/d..").as_deref(), Some("static//a..b/..c/d.."));
+
+        /*- Absolute paths are joined under the dir -*/
+        assert_eq!(safe_join("static", "//etc/passwd").as_deref(), Some("static///etc/passwd"));
+
+        assert_eq!(safe_join("static", ".."), None);
+        assert_eq!(safe_join("static", "/../secret"), None);
+        assert_eq!(safe_join("static", "/css/../../secret"), None);
+        assert_eq!(safe_join("static", "/css/.."), None);
+        assert_eq!(safe_join("static", "\\..\\secret"), None);
+        assert_eq!(safe_join("static", "/C:/Windows/win.ini"), None);
+        assert_eq!(safe_join("static", "c:\\Windows"), None);
+        assert_eq!(safe_join("static", "/file.txt\0.png"), None);
+    }
+
+    /*- Send raw bytes over a local connection, and read them like a request -*/
+    fn read_raw(raw:&[u8], limits:&Limits) -> Result<ReadRequest, (Vec<u8>, u16)> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(raw).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let (mut stream, _) = listener.accept().unwrap();
+        read_request(&mut stream, limits)
+    }
+
+    #[test]
+    fn request_bodies_are_read_up_to_the_content_length() {
+        let limits = Limits { max_body_size: 8, ..Default::default() };
+        let status = |raw:&str| read_raw(raw.as_bytes(), &limits).err().map(|(_, status)| status);
+
+        let (data, streamed) = read_raw(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n", &limits).unwrap();
+        assert_eq!(data, b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+        assert_eq!(streamed, None);
+
+        assert_eq!(status("POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\n123456789"), Some(413));
+        assert_eq!(status("POST / HTTP/1.1\r\nContent-Length: five\r\n\r\nhello"), Some(400));
+        assert_eq!(status("POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\nhello!"), Some(400));
+        assert_eq!(status("POST / HTTP/1.1\r\nContent-Length: 5\r\ncontent-length: 5\r\n\r\nhello"), None);
+    }
+
+    #[test]
+    fn transfer_encodings_are_refused() {
+        let status = |raw:&str| read_raw(raw.as_bytes(), &Limits::default()).err().map(|(_, status)| status);
+
+        assert_eq!(status("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n"), Some(501));
+        assert_eq!(status("POST / HTTP/1.1\r\ntransfer-encoding: gzip, chunked\r\n\r\n"), Some(501));
+        assert_eq!(status("POST / HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n"), Some(400));
+    }
+
+    #[test]
+    fn etags_answer_304() {
+        let request = |if_none_match:&str| Request::parse(format!("GET /app.js HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", if_none_match), None);
+        let response = || Response::content(200, ResponseType::JavaScript, "let a = 1;");
+        let tag = etag(b"let a = 1;");
+
+        let fresh = with_etag(&request("\"other\""), response());
+        assert_eq!(fresh.status, 200);
+        assert_eq!(fresh.get_header("ETag"), Some(tag.as_str()));
+        assert_eq!(fresh.body, b"let a = 1;");
+
+        /*- Compared weakly, so a compressed response's tag matches too -*/
+        for if_none_match in [tag.clone(), format!("W/{}", tag), format!("\"other\", {}", tag), String::from("*")] {
+            let cached = with_etag(&request(&if_none_match), response());
+            assert_eq!(cached.status, 304, "{}", if_none_match);
+            assert!(cached.body.is_empty());
+            assert_eq!(cached.get_header("ETag"), Some(tag.as_str()));
+            assert!(cached.get_header("Content-Type").is_some());
+        };
+
+        /*- Only 200s get a tag -*/
+        let partial = with_etag(&request(&tag), Response::new(206));
+        assert_eq!(partial.status, 206);
+        assert_eq!(partial.get_header("ETag"), None);
+    }
+}
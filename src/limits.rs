@@ -0,0 +1,33 @@
+/// Size limits for every request, set through `ServerOptions::limits`. Requests
+/// over them are answered without being read any further: `431` for the head,
+/// and `413` for the body. Stacks can lower them for their endpoints, see `StackOptions`
+/// # Examples
+/// ```ignore
+/// limits: Limits { max_body_size: 10 * 1024 * 1024, ..Default::default() },
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// The largest head (request line and headers), in bytes
+    pub max_header_size:usize,
+    /// The most headers a request can have
+    pub max_headers:usize,
+    /// The largest body that is read into memory, in bytes. Multipart bodies are
+    /// streamed instead, and limited by `max_multipart_size`
+    pub max_body_size:usize,
+    /// The largest multipart body, in bytes. Checked against the `Content-Length`
+    /// before routing, so it applies even to endpoints that never read the body.
+    /// `MultipartLimits::max_total_size` defaults to the same, and it and
+    /// `StackOptions::max_body` can only lower it
+    pub max_multipart_size:usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_header_size: 16 * 1024,
+            max_headers: 100,
+            max_body_size: 2 * 1024 * 1024,
+            max_multipart_size: 100 * 1024 * 1024,
+        }
+    }
+}
@@ -0,0 +1,99 @@
+/*- Imports -*/
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use percent_encoding::{ utf8_percent_encode, AsciiSet, CONTROLS };
+use crate::{ Request, Response, ResponseType, access_log::json_escape };
+
+/*- The icons are baked into the binary so listings work without any static files -*/
+const FILE_ICON:&[u8] = include_bytes!("../data/images/file.png");
+const FOLDER_ICON:&[u8] = include_bytes!("../data/images/folder.png");
+
+/*- Characters that have to be escaped in the links of a listing (but not /) -*/
+const PATH_SEGMENT:&AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>').add(b'?').add(b'`').add(b'{').add(b'}');
+
+/*- One row of a listing -*/
+struct ListingEntry {
+    name:String,
+    is_dir:bool,
+    size:u64,
+    modified:u64,
+}
+
+/*- Read a directory, folders first and hidden files skipped -*/
+fn read_entries(full_path:&str) -> Option<Vec<ListingEntry>> {
+    let mut entries:Vec<ListingEntry> = fs::read_dir(full_path).ok()?.filter_map(|entry| {
+        let entry = entry.ok()?;
+        let name = entry.file_name().to_str()?.to_string();
+        if name.starts_with('.') { return None; };
+
+        let metadata = entry.metadata().ok()?;
+        Some(ListingEntry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        })
+    }).collect();
+
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then(a.name.cmp(&b.name)));
+    Some(entries)
+}
+
+/// A generated listing of a directory. Clients asking for `application/json`
+/// get a json array, everyone else a html page
+pub fn listing_response(request:&Request, full_path:&str) -> Option<Response> {
+    if !Path::new(full_path).is_dir() { return None; };
+    let entries = read_entries(full_path)?;
+
+    /*- Json -*/
+    if request.header("Accept").map(|a| a.contains("application/json")).unwrap_or(false) {
+        let items:Vec<String> = entries.iter().map(|e| format!(
+            "{{\"name\":\"{}\",\"type\":\"{}\",\"size\":{},\"modified\":{}}}",
+            json_escape(&e.name), if e.is_dir { "directory" } else { "file" }, e.size, e.modified
+        )).collect();
+
+        return Some(Response::content(200, ResponseType::Json, format!("[{}]", items.join(","))));
+    };
+
+    /*- Html -*/
+    let file_icon = format!("data:image/png;base64,{}", base64::encode(FILE_ICON));
+    let folder_icon = format!("data:image/png;base64,{}", base64::encode(FOLDER_ICON));
+    let title = html_escape(&request.path);
+
+    let mut rows = String::new();
+    if request.path != "/" {
+        rows.push_str(&format!("<tr><td><img src=\"{}\"></td><td><a href=\"../\">../</a></td><td></td></tr>", folder_icon));
+    };
+    /*- Links start with ./ so that a name like `javascript:...` can't become a scheme -*/
+    for entry in entries.iter() {
+        let name = if entry.is_dir { format!("{}/", entry.name) } else { entry.name.clone() };
+        rows.push_str(&format!(
+            "<tr><td><img src=\"{}\"></td><td><a href=\"./{}\">{}</a></td><td>{}</td></tr>",
+            if entry.is_dir { &folder_icon } else { &file_icon },
+            html_escape(&utf8_percent_encode(&name, PATH_SEGMENT).to_string()),
+            html_escape(&name),
+            if entry.is_dir { String::new() } else { entry.size.to_string() },
+        ));
+    };
+
+    Some(Response::content(200, ResponseType::Html, format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Index of {0}</title>\
+        <style>body{{font-family:sans-serif}}img{{width:16px;height:16px}}td{{padding:2px 12px 2px 0}}</style>\
+        </head><body><h1>Index of {0}</h1><table>{1}</table></body></html>",
+        title, rows
+    )))
+}
+
+/*- Escape text so it can be put inside of html -*/
+pub(crate) fn html_escape(input:&str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -0,0 +1,76 @@
+/*- Imports -*/
+use crate::{ request::Request, response::Response };
+
+/// Runs before the route handler. Returning a response
+/// will skip the handler and send that response instead
+pub type Before = fn(&mut Request) -> Option<Response>;
+
+/// Runs after the route handler, and can modify the response before it's sent
+pub type After = fn(&Request, &mut Response);
+
+/// A layer around the route handlers. Middleware can be added to
+/// `ServerOptions` (runs for every request) or to a stack with
+/// `RouteRoot::StackWith` (runs for every endpoint inside of it).
+///
+/// `before` functions run from the outermost layer and in, `after`
+/// functions run in reverse, so the first layer gets the last word.
+///
+/// Note that `RouteValue::Function` endpoints write straight to the
+/// stream, so only the `before` part of a layer applies to them.
+/// # Examples
+/// ```ignore
+/// fn require_token(request:&mut Request) -> Option<Response> {
+///     match request.header("Authorization") {
+///         Some(_) => None,
+///         None => Some(Response::new(401)),
+///     }
+/// }
+///
+/// let auth = Middleware::before(require_token);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Middleware {
+    pub before:Option<Before>,
+    pub after:Option<After>,
+}
+
+impl Middleware {
+
+    /// A layer with both a before and an after function
+    pub const fn new(before:Before, after:After) -> Middleware {
+        Middleware { before: Some(before), after: Some(after) }
+    }
+
+    /// A layer that only inspects / short-circuits requests
+    pub const fn before(before:Before) -> Middleware {
+        Middleware { before: Some(before), after: None }
+    }
+
+    /// A layer that only post-processes responses
+    pub const fn after(after:After) -> Middleware {
+        Middleware { before: None, after: Some(after) }
+    }
+}
+
+/// Run the `before` functions in order. If one of them short-circuits, the response
+/// is returned together with how many layers that ran (and should get their `after`)
+pub(crate) fn run_before(layers:&[Middleware], request:&mut Request) -> Result<(), (usize, Response)> {
+    for (index, layer) in layers.iter().enumerate() {
+        if let Some(before) = layer.before {
+            if let Some(response) = before(request) {
+                return Err((index + 1, response));
+            };
+        };
+    };
+
+    Ok(())
+}
+
+/// Run the `after` functions in reverse order
+pub(crate) fn run_after(layers:&[Middleware], request:&Request, response:&mut Response) {
+    for layer in layers.iter().rev() {
+        if let Some(after) = layer.after {
+            after(request, response);
+        };
+    };
+}
@@ -0,0 +1,194 @@
+/*- Imports -*/
+use std::path::Path;
+
+/*- Extension -> mime type. Keep extensions lowercase, lookups are case-insensitive -*/
+const MIME_TYPES:&[(&str, &str)] = &[
+    /*- Text -*/
+    ("html", "text/html"),              ("htm", "text/html"),
+    ("xhtml", "application/xhtml+xml"), ("css", "text/css"),
+    ("js", "text/javascript"),          ("mjs", "text/javascript"),
+    ("cjs", "text/javascript"),         ("txt", "text/plain"),
+    ("md", "text/markdown"),            ("markdown", "text/markdown"),
+    ("csv", "text/csv"),                ("tsv", "text/tab-separated-values"),
+    ("ics", "text/calendar"),           ("vtt", "text/vtt"),
+    ("xml", "application/xml"),         ("rss", "application/rss+xml"),
+    ("atom", "application/atom+xml"),   ("json", "application/json"),
+    ("map", "application/json"),        ("jsonld", "application/ld+json"),
+    ("webmanifest", "application/manifest+json"),
+    ("yml", "application/yaml"),        ("yaml", "application/yaml"),
+    ("toml", "application/toml"),
+
+    /*- Images -*/
+    ("png", "image/png"),               ("apng", "image/apng"),
+    ("jpg", "image/jpeg"),              ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),               ("webp", "image/webp"),
+    ("avif", "image/avif"),             ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),            ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),              ("tiff", "image/tiff"),
+    ("heic", "image/heic"),
+
+    /*- Fonts -*/
+    ("woff", "font/woff"),              ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),                ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+
+    /*- Audio -*/
+    ("mp3", "audio/mpeg"),              ("wav", "audio/wav"),
+    ("ogg", "audio/ogg"),               ("oga", "audio/ogg"),
+    ("opus", "audio/opus"),             ("flac", "audio/flac"),
+    ("aac", "audio/aac"),               ("m4a", "audio/mp4"),
+    ("weba", "audio/webm"),             ("mid", "audio/midi"),
+    ("midi", "audio/midi"),
+
+    /*- Video -*/
+    ("mp4", "video/mp4"),               ("m4v", "video/mp4"),
+    ("webm", "video/webm"),             ("ogv", "video/ogg"),
+    ("mov", "video/quicktime"),         ("avi", "video/x-msvideo"),
+    ("mkv", "video/x-matroska"),        ("mpeg", "video/mpeg"),
+    ("mpg", "video/mpeg"),              ("3gp", "video/3gpp"),
+
+    /*- Applications & archives -*/
+    ("wasm", "application/wasm"),       ("pdf", "application/pdf"),
+    ("zip", "application/zip"),         ("gz", "application/gzip"),
+    ("tgz", "application/gzip"),        ("tar", "application/x-tar"),
+    ("bz2", "application/x-bzip2"),     ("xz", "application/x-xz"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),     ("br", "application/x-brotli"),
+    ("zst", "application/zstd"),        ("rtf", "application/rtf"),
+    ("epub", "application/epub+zip"),   ("jar", "application/java-archive"),
+    ("doc", "application/msword"),      ("xls", "application/vnd.ms-excel"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("odp", "application/vnd.oasis.opendocument.presentation"),
+    ("sh", "application/x-sh"),         ("bin", "application/octet-stream"),
+];
+
+/// What sniffing gives content it doesn't recognize, and that isn't readable text
+pub const DEFAULT_MIME:&str = "application/octet-stream";
+
+/// Get the mime type of a path by its extension, like `text/css` for `style.css`
+pub fn guess_mime(path:&str) -> Option<&'static str> {
+    let extension = Path::new(path).extension()?.to_str()?;
+
+    MIME_TYPES.iter()
+        .find(|(ext, _)| ext.eq_ignore_ascii_case(extension))
+        .map(|(_, mime)| *mime)
+}
+
+/// Guess the mime type by looking at the first bytes of some content
+pub fn sniff_mime(content:&[u8]) -> &'static str {
+    /*- Magic numbers -*/
+    let signatures:&[(&[u8], &'static str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xFF\xD8\xFF", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1F\x8B", "application/gzip"),
+        (b"\x00asm", "application/wasm"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+        (b"ID3", "audio/mpeg"),
+        (b"OggS", "audio/ogg"),
+        (b"\x1A\x45\xDF\xA3", "video/webm"),
+    ];
+    if let Some(mime) = signatures.iter().find(|(magic, _)| content.starts_with(magic)).map(|(_, mime)| *mime) {
+        return mime;
+    };
+    if content.len() >= 12 && &content[0..4] == b"RIFF" && &content[8..12] == b"WEBP" {
+        return "image/webp";
+    };
+    if content.len() >= 12 && &content[4..8] == b"ftyp" {
+        return "video/mp4";
+    };
+
+    /*- Markup is recognized by how it starts, ignoring whitespace and case -*/
+    let start = String::from_utf8_lossy(&content[..content.len().min(256)]).trim_start().to_ascii_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        return "text/html";
+    };
+    if start.starts_with("<svg") {
+        return "image/svg+xml";
+    };
+    if start.starts_with("<?xml") {
+        return "application/xml";
+    };
+
+    /*- Anything else that is readable is text. The sample might
+        cut a character in half, which is fine -*/
+    let sample = &content[..content.len().min(1024)];
+    let readable = match std::str::from_utf8(sample) {
+        Ok(_) => true,
+        Err(error) => error.error_len().is_none(),
+    };
+    if readable && !sample.contains(&0) {
+        return "text/plain";
+    };
+
+    DEFAULT_MIME
+}
+
+/// Add `; charset=utf-8` to textual mime types that don't have a charset already
+pub fn with_charset(mime:&str) -> String {
+    let textual = mime.starts_with("text/")
+        || mime.ends_with("+xml")
+        || mime.ends_with("+json")
+        || matches!(mime, "application/json" | "application/javascript" | "application/xml" | "application/yaml" | "application/toml");
+
+    if textual && !mime.contains("charset") {
+        format!("{}; charset=utf-8", mime)
+    } else {
+        mime.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mime_by_extension() {
+        assert_eq!(guess_mime("static/style.CSS"), Some("text/css"));
+        assert_eq!(guess_mime("archive.tar.gz"), Some("application/gzip"));
+        assert_eq!(guess_mime("Makefile"), None);
+        assert_eq!(guess_mime("file.unknown-extension"), None);
+    }
+
+    #[test]
+    fn sniffs_magic_numbers_and_markup() {
+        assert_eq!(sniff_mime(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "image/png");
+        assert_eq!(sniff_mime(b"RIFF\x24\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(sniff_mime(b"\0\0\0\x18ftypmp42"), "video/mp4");
+        assert_eq!(sniff_mime(b"\n  <!DOCTYPE HTML><title>"), "text/html");
+        assert_eq!(sniff_mime(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>"), "image/svg+xml");
+
+        /*- Too short to be a riff file -*/
+        assert_eq!(sniff_mime(b"RIFF"), "text/plain");
+    }
+
+    #[test]
+    fn text_and_binary_content() {
+        assert_eq!(sniff_mime(b""), "text/plain");
+        assert_eq!(sniff_mime("plain text, with ünïcode".as_bytes()), "text/plain");
+        assert_eq!(sniff_mime(b"text\0with a nul"), DEFAULT_MIME);
+        assert_eq!(sniff_mime(b"\xC3\x28 invalid utf-8"), DEFAULT_MIME);
+
+        /*- A character cut in half by the end of the sample is still text -*/
+        let mut content = vec![b'a'; 1023];
+        content.extend_from_slice("é and more".as_bytes());
+        assert_eq!(sniff_mime(&content), "text/plain");
+    }
+
+    #[test]
+    fn charset_is_added_to_textual_types() {
+        assert_eq!(with_charset("text/html"), "text/html; charset=utf-8");
+        assert_eq!(with_charset("image/svg+xml"), "image/svg+xml; charset=utf-8");
+        assert_eq!(with_charset("text/plain; charset=latin1"), "text/plain; charset=latin1");
+        assert_eq!(with_charset("image/png"), "image/png");
+    }
+}
@@ -0,0 +1,587 @@
+/*- Imports -*/
+use std::{ env, fmt, fs, io };
+use std::io::{ Read, Write };
+use std::path::{ Path, PathBuf };
+use percent_encoding::percent_decode_str;
+use crate::{ Request, Response, ResponseType, Limits };
+
+/// Limits for `multipart/form-data` bodies. Going over them fails with `413`
+#[derive(Debug, Clone)]
+pub struct MultipartLimits {
+    /// The largest file (a part with a filename)
+    pub max_file_size:u64,
+    /// The largest field (a part without a filename)
+    pub max_field_size:u64,
+    /// The largest body, all parts together. Defaults to `Limits::max_multipart_size`,
+    /// which is checked before routing, so raising this past it has no effect
+    pub max_total_size:u64,
+    pub max_parts:usize,
+    /// `spool` keeps parts up to this size in memory, larger ones go to a temp file
+    pub memory_threshold:usize,
+    /// Where spooled parts are written, the system temp dir by default
+    pub temp_dir:PathBuf,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> MultipartLimits {
+        MultipartLimits {
+            max_file_size: 100 * 1024 * 1024,
+            max_field_size: 1024 * 1024,
+            max_total_size: Limits::default().max_multipart_size as u64,
+            max_parts: 100,
+            memory_threshold: 256 * 1024,
+            temp_dir: env::temp_dir(),
+        }
+    }
+}
+
+/// Why a multipart body couldn't be read, and the status to answer with
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultipartError {
+    pub status:u16,
+    pub message:String,
+}
+
+impl MultipartError {
+    fn new(status:u16, message:&str) -> MultipartError {
+        MultipartError { status, message: message.to_string() }
+    }
+
+    /// The response to send back
+    pub fn into_response(self) -> Response {
+        tracing::debug!(status = self.status, error = %self.message, "bad multipart body");
+        Response::error(self.status, &ResponseType::Text)
+    }
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.status)
+    }
+}
+
+impl std::error::Error for MultipartError {}
+
+/*- Parts are read through io::Read, so their errors travel inside of io::Error -*/
+impl From<io::Error> for MultipartError {
+    fn from(error:io::Error) -> MultipartError {
+        match error.get_ref().and_then(|inner| inner.downcast_ref::<MultipartError>()) {
+            Some(error) => error.clone(),
+            None => MultipartError::new(400, &format!("couldn't read the body: {}", error)),
+        }
+    }
+}
+
+impl From<MultipartError> for io::Error {
+    fn from(error:MultipartError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /*- Before the first boundary -*/
+    Preamble,
+    /*- Inside of the body of a part -*/
+    Body,
+    /*- Right after a boundary -*/
+    Boundary,
+    Done,
+}
+
+/// A `multipart/form-data` body, read one part at a time from the stream.
+/// Get one with `Request::multipart`
+/// # Examples
+/// ```ignore
+/// fn upload(request:&Request) -> Response {
+///     let mut multipart = match request.multipart() {
+///         Ok(multipart) => multipart,
+///         Err(response) => return response,
+///     };
+///
+///     loop {
+///         let mut part = match multipart.next_part() {
+///             Ok(Some(part)) => part,
+///             Ok(None) => break,
+///             Err(error) => return error.into_response(),
+///         };
+///         if part.filename.is_some() {
+///             if let Err(error) = part.save(format!("./uploads/{}", random_name())) { return error.into_response(); };
+///         };
+///     }
+///     Response::new(201)
+/// }
+/// ```
+pub struct Multipart {
+    reader:Box<dyn Read + Send>,
+    /*- `\r\n--boundary`, which ends every part -*/
+    delimiter:Vec<u8>,
+    buffer:Vec<u8>,
+    limits:MultipartLimits,
+    state:State,
+    total:u64,
+    parts:usize,
+    part_read:u64,
+    part_limit:u64,
+}
+
+/// One part of a multipart body. Its content is read with `io::Read`
+/// (or `bytes`, `text`, `save` and `spool`), until the next part is asked for
+pub struct Part<'a> {
+    /// The name of the form field
+    pub name:String,
+    /// Only set for files, without any directories the browser might include.
+    /// Never empty, `.` or `..`
+    pub filename:Option<String>,
+    pub content_type:Option<String>,
+    pub headers:Vec<(String, String)>,
+    multipart:&'a mut Multipart,
+}
+
+/// A part that was read completely, see `Part::spool`
+#[derive(Debug)]
+pub struct SavedPart {
+    pub name:String,
+    pub filename:Option<String>,
+    pub content_type:Option<String>,
+    pub data:PartData,
+}
+
+/// Where the content of a `SavedPart` is
+#[derive(Debug)]
+pub enum PartData {
+    Memory(Vec<u8>),
+    File(TempFile),
+}
+
+/// A file in the temp dir, which is removed when this is dropped (unless it's `persist`ed)
+#[derive(Debug)]
+pub struct TempFile {
+    path:PathBuf,
+    size:u64,
+}
+
+impl Multipart {
+
+    /// Parse a body from any reader, `boundary` is the one from the `Content-Type`
+    pub fn new(reader:Box<dyn Read + Send>, boundary:&str, limits:MultipartLimits) -> Multipart {
+        Multipart {
+            reader,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            /*- The first boundary isn't preceded by a newline, so one is added -*/
+            buffer: b"\r\n".to_vec(),
+            limits,
+            state: State::Preamble,
+            total: 0,
+            parts: 0,
+            part_read: 0,
+            part_limit: u64::MAX,
+        }
+    }
+
+    /// The next part, or `None` after the last one. Whatever wasn't read of the previous part is skipped
+    pub fn next_part(&mut self) -> Result<Option<Part<'_>>, MultipartError> {
+        if matches!(self.state, State::Preamble | State::Body) {
+            self.state = State::Body;
+            self.part_limit = u64::MAX;
+            let mut sink = [0u8; 8192];
+            while self.read_body(&mut sink)? > 0 {};
+        };
+        if self.state == State::Done { return Ok(None); };
+
+        /*- `--` after the boundary means that was the last part -*/
+        self.fill_to(2)?;
+        if self.buffer.starts_with(b"--") {
+            self.state = State::Done;
+            return Ok(None);
+        };
+
+        /*- Headers, up to the empty line. The buffer starts with the newline after the boundary -*/
+        let header_end = loop {
+            if let Some(position) = find(&self.buffer, b"\r\n\r\n") { break position; };
+            if self.buffer.len() > 16 * 1024 { return Err(MultipartError::new(431, "part headers are too large")); };
+            if !self.fill()? { return Err(MultipartError::new(400, "the body ended inside of part headers")); };
+        };
+        let head = String::from_utf8_lossy(&self.buffer[..header_end]).to_string();
+        self.buffer.drain(..header_end + 4);
+
+        let headers:Vec<(String, String)> = head.split("\r\n")
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect();
+        let header = |name:&str| headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.clone());
+
+        let disposition = disposition_params(&header("Content-Disposition").unwrap_or_default());
+        let param = |name:&str| disposition.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.clone());
+        let name = param("name").ok_or_else(|| MultipartError::new(400, "a part has no name"))?;
+        let filename = param("filename*")
+            .and_then(|value| value.split_once("''").map(|(_, encoded)| percent_decode_str(encoded).decode_utf8_lossy().to_string()))
+            .or_else(|| param("filename"))
+            /*- Only the name, without any directories. Nothing is left of `.`, `..` or `dir/`,
+                and an empty file input sends `filename=""`, those parts are fields -*/
+            .map(|filename| filename.rsplit(['/', '\\']).next().unwrap_or("").to_string())
+            .filter(|filename| !matches!(filename.as_str(), "" | "." | ".."));
+
+        self.parts += 1;
+        if self.parts > self.limits.max_parts {
+            return Err(MultipartError::new(413, "too many parts"));
+        };
+
+        self.state = State::Body;
+        self.part_read = 0;
+        self.part_limit = if filename.is_some() { self.limits.max_file_size } else { self.limits.max_field_size };
+
+        Ok(Some(Part { name, filename, content_type: header("Content-Type"), headers, multipart: self }))
+    }
+
+    /// Read every part, with `Part::spool`
+    pub fn spool_all(mut self) -> Result<Vec<SavedPart>, MultipartError> {
+        let mut parts = Vec::new();
+        while let Some(mut part) = self.next_part()? {
+            parts.push(part.spool()?);
+        };
+
+        Ok(parts)
+    }
+
+    /*- Read more of the body into the buffer, false at the end of it -*/
+    fn fill(&mut self) -> Result<bool, MultipartError> {
+        let mut chunk = [0u8; 8192];
+        let read = self.reader.read(&mut chunk)?;
+        if read == 0 { return Ok(false); };
+
+        self.total += read as u64;
+        if self.total > self.limits.max_total_size {
+            return Err(MultipartError::new(413, "the body is too large"));
+        };
+
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(true)
+    }
+
+    fn fill_to(&mut self, size:usize) -> Result<(), MultipartError> {
+        while self.buffer.len() < size {
+            if !self.fill()? { return Err(MultipartError::new(400, "the body ended too early")); };
+        };
+        Ok(())
+    }
+
+    /*- Read the body of the current part, 0 once the boundary after it is reached -*/
+    fn read_body(&mut self, output:&mut [u8]) -> Result<usize, MultipartError> {
+        if self.state != State::Body || output.is_empty() { return Ok(0); };
+
+        loop {
+            if let Some(position) = find(&self.buffer, &self.delimiter) {
+                if position == 0 {
+                    self.buffer.drain(..self.delimiter.len());
+                    self.state = State::Boundary;
+                    return Ok(0);
+                };
+                return self.take(output, position);
+            };
+
+            /*- Everything that can't be the start of the delimiter is part of the body -*/
+            let safe = self.buffer.len().saturating_sub(self.delimiter.len() - 1);
+            if safe > 0 { return self.take(output, safe); };
+            if !self.fill()? { return Err(MultipartError::new(400, "the body ended inside of a part")); };
+        }
+    }
+
+    fn take(&mut self, output:&mut [u8], available:usize) -> Result<usize, MultipartError> {
+        let size = available.min(output.len());
+        self.part_read += size as u64;
+        if self.part_read > self.part_limit {
+            return Err(MultipartError::new(413, "a part is too large"));
+        };
+
+        output[..size].copy_from_slice(&self.buffer[..size]);
+        self.buffer.drain(..size);
+        Ok(size)
+    }
+}
+
+impl Read for Part<'_> {
+    fn read(&mut self, buffer:&mut [u8]) -> io::Result<usize> {
+        self.multipart.read_body(buffer).map_err(io::Error::from)
+    }
+}
+
+impl Part<'_> {
+
+    /// Read the rest of the part into memory
+    pub fn bytes(&mut self) -> Result<Vec<u8>, MultipartError> {
+        let mut content = Vec::new();
+        self.read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    /// Read the rest of the part as (utf-8) text
+    pub fn text(&mut self) -> Result<String, MultipartError> {
+        String::from_utf8(self.bytes()?).map_err(|_| MultipartError::new(400, "a text part isn't utf-8"))
+    }
+
+    /// Write the rest of the part to a file, returns the number of bytes
+    pub fn save<P:AsRef<Path>>(&mut self, path:P) -> Result<u64, MultipartError> {
+        let mut file = fs::File::create(path).map_err(|error| MultipartError::new(500, &error.to_string()))?;
+        copy(self, &mut file)
+    }
+
+    /// Read the rest of the part into memory, or into a temp file
+    /// if it's larger than `MultipartLimits::memory_threshold`
+    pub fn spool(&mut self) -> Result<SavedPart, MultipartError> {
+        let threshold = self.multipart.limits.memory_threshold as u64;
+        let mut content = Vec::new();
+        (&mut *self).take(threshold + 1).read_to_end(&mut content)?;
+
+        let data = if content.len() as u64 > threshold {
+            let (mut temp_file, mut file) = TempFile::create(&self.multipart.limits.temp_dir)?;
+            file.write_all(&content).map_err(|error| MultipartError::new(500, &error.to_string()))?;
+            temp_file.size = content.len() as u64 + copy(self, &mut file)?;
+            PartData::File(temp_file)
+        } else {
+            PartData::Memory(content)
+        };
+
+        Ok(SavedPart {
+            name: self.name.clone(),
+            filename: self.filename.clone(),
+            content_type: self.content_type.clone(),
+            data,
+        })
+    }
+}
+
+impl SavedPart {
+    /// The size of the content
+    pub fn len(&self) -> u64 {
+        match &self.data {
+            PartData::Memory(content) => content.len() as u64,
+            PartData::File(file) => file.size,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Read the content, from memory or from the temp file
+    pub fn reader(&self) -> io::Result<Box<dyn Read + Send + '_>> {
+        match &self.data {
+            PartData::Memory(content) => Ok(Box::new(&content[..])),
+            PartData::File(file) => Ok(Box::new(fs::File::open(&file.path)?)),
+        }
+    }
+}
+
+impl TempFile {
+    fn create(dir:&Path) -> Result<(TempFile, fs::File), MultipartError> {
+        let path = dir.join(format!("fastserve-{:016x}.upload", rand::random::<u64>()));
+        let file = fs::OpenOptions::new().write(true).create_new(true).open(&path)
+            .map_err(|error| MultipartError::new(500, &format!("couldn't create a temp file: {}", error)))?;
+
+        Ok((TempFile { path, size: 0 }, file))
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// Move the file somewhere else, so that it's kept
+    pub fn persist<P:AsRef<Path>>(self, to:P) -> io::Result<()> {
+        /*- Renaming doesn't work across file systems, then it's copied -*/
+        if fs::rename(&self.path, to.as_ref()).is_err() {
+            fs::copy(&self.path, to.as_ref())?;
+        };
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).unwrap_or(());
+    }
+}
+
+impl Request {
+
+    /// Read a `multipart/form-data` body with the default `MultipartLimits`
+    pub fn multipart(&self) -> Result<Multipart, Response> {
+        self.multipart_with(MultipartLimits::default())
+    }
+
+    /// Read a `multipart/form-data` body. Fails with `415` if it isn't one, or
+    /// `413` if the `Content-Length` is already over `max_total_size`. The body
+    /// is left on the stream for multipart requests, so it can only be read once
+    pub fn multipart_with(&self, limits:MultipartLimits) -> Result<Multipart, Response> {
+        if self.headers.mime().as_deref() != Some("multipart/form-data") {
+            return Err(MultipartError::new(415, "not a multipart/form-data body").into_response());
+        };
+
+        let params = self.headers.content_type().unwrap_or("").split(';').skip(1);
+        let boundary = match params.map(str::trim).find_map(|param| param.strip_prefix("boundary=")) {
+            Some(boundary) if !boundary.trim_matches('"').is_empty() => boundary.trim_matches('"').to_string(),
+            _ => return Err(MultipartError::new(400, "the boundary is missing").into_response()),
+        };
+
+        if self.headers.content_length().unwrap_or(0) > limits.max_total_size {
+            return Err(MultipartError::new(413, "the body is too large").into_response());
+        };
+
+        Ok(Multipart::new(self.body_reader(), &boundary, limits))
+    }
+}
+
+/*- Like io::copy, but keeping the multipart errors -*/
+fn copy<W:Write>(part:&mut Part, output:&mut W) -> Result<u64, MultipartError> {
+    let mut buffer = [0u8; 8192];
+    let mut written = 0;
+
+    loop {
+        let read = part.multipart.read_body(&mut buffer)?;
+        if read == 0 { return Ok(written); };
+        output.write_all(&buffer[..read]).map_err(|error| MultipartError::new(500, &error.to_string()))?;
+        written += read as u64;
+    }
+}
+
+/*- The position of a needle in a haystack -*/
+fn find(haystack:&[u8], needle:&[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/*- `form-data; name="field"; filename="a;b.txt"` into its parameters, quotes removed -*/
+fn disposition_params(value:&str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut chars = value.chars().peekable();
+
+    /*- Skip the disposition type -*/
+    for c in chars.by_ref() { if c == ';' { break; }; };
+
+    loop {
+        let key:String = chars.by_ref().take_while(|&c| c != '=').collect::<String>().trim().to_string();
+        if key.is_empty() { break; };
+        while chars.peek() == Some(&' ') { chars.next(); };
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    /*- Browsers don't escape backslashes (windows paths), only quotes -*/
+                    '\\' if chars.peek() == Some(&'"') => value.push(chars.next().unwrap_or('"')),
+                    '"' => break,
+                    c => value.push(c),
+                };
+            };
+            for c in chars.by_ref() { if c == ';' { break; }; };
+        } else {
+            value = chars.by_ref().take_while(|&c| c != ';').collect::<String>().trim().to_string();
+        };
+
+        params.push((key, value));
+    };
+
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*- Hands out the body a few bytes at a time, so boundaries get split across reads -*/
+    struct Chunked {
+        data:Vec<u8>,
+        size:usize,
+    }
+
+    impl Read for Chunked {
+        fn read(&mut self, buffer:&mut [u8]) -> io::Result<usize> {
+            let size = self.size.min(buffer.len()).min(self.data.len());
+            buffer[..size].copy_from_slice(&self.data[..size]);
+            self.data.drain(..size);
+            Ok(size)
+        }
+    }
+
+    fn multipart(body:&str, chunk:usize) -> Multipart {
+        let reader = Chunked { data: body.as_bytes().to_vec(), size: chunk };
+        Multipart::new(Box::new(reader), "XyZ", MultipartLimits::default())
+    }
+
+    const BODY:&str = "--XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        hello\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        line one\r\nline two\r\n--XyY\r\n\
+        --XyZ--\r\n";
+
+    #[test]
+    fn boundary_split_across_reads() {
+        for chunk in [1, 2, 3, 5, 7, 64] {
+            let mut multipart = multipart(BODY, chunk);
+
+            let mut title = multipart.next_part().unwrap().unwrap();
+            assert_eq!(title.name, "title");
+            assert_eq!(title.text().unwrap(), "hello");
+
+            let mut file = multipart.next_part().unwrap().unwrap();
+            assert_eq!(file.filename.as_deref(), Some("a.txt"));
+            assert_eq!(file.content_type.as_deref(), Some("text/plain"));
+            assert_eq!(file.text().unwrap(), "line one\r\nline two\r\n--XyY", "chunk size {}", chunk);
+
+            assert!(multipart.next_part().unwrap().is_none());
+        };
+    }
+
+    #[test]
+    fn crlf_inside_a_part_body() {
+        let body = "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n\r\n\r\n--Xy\r\n\r\n--XyZ--\r\n";
+        let mut multipart = multipart(body, 4);
+
+        let mut part = multipart.next_part().unwrap().unwrap();
+        assert_eq!(part.text().unwrap(), "\r\n\r\n--Xy\r\n");
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn missing_closing_boundary() {
+        let body = "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nno end";
+        let mut first = multipart(body, 3);
+        let mut part = first.next_part().unwrap().unwrap();
+        assert_eq!(Part::bytes(&mut part).unwrap_err().status, 400);
+
+        /*- A boundary, but the body ends before saying whether it was the last one -*/
+        let body = "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue\r\n--XyZ";
+        let mut second = multipart(body, 3);
+        assert_eq!(second.next_part().unwrap().unwrap().text().unwrap(), "value");
+        assert_eq!(second.next_part().err().map(|error| error.status), Some(400));
+    }
+
+    #[test]
+    fn directories_are_stripped_from_filenames() {
+        let filenames = [
+            ("../../etc/passwd", "passwd"),
+            ("..\\..\\windows\\win.ini", "win.ini"),
+            ("C:\\Users\\me\\photo.png", "photo.png"),
+            ("/absolute/path.txt", "path.txt"),
+            ("plain.txt", "plain.txt"),
+            /*- Nothing left, these are fields -*/
+            ("..", ""),
+            (".", ""),
+            ("dir/..", ""),
+            ("dir/", ""),
+            ("", ""),
+        ];
+
+        for (sent, expected) in filenames {
+            let body = format!("--XyZ\r\nContent-Disposition: form-data; name=\"f\"; filename=\"{}\"\r\n\r\nx\r\n--XyZ--\r\n", sent);
+            let mut multipart = multipart(&body, 16);
+            let part = multipart.next_part().unwrap().unwrap();
+            let expected = if expected.is_empty() { None } else { Some(expected) };
+            assert_eq!(part.filename.as_deref(), expected, "filename {:?}", sent);
+        };
+
+        /*- filename* is percent-decoded before the directories are stripped -*/
+        let body = "--XyZ\r\nContent-Disposition: form-data; name=\"f\"; filename*=UTF-8''..%2F..%2Fsecret\r\n\r\nx\r\n--XyZ--\r\n";
+        let mut multipart = multipart(body, 16);
+        assert_eq!(multipart.next_part().unwrap().unwrap().filename.as_deref(), Some("secret"));
+    }
+}
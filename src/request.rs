@@ -0,0 +1,121 @@
+/*- Imports -*/
+use std::collections::HashMap;
+use std::io::{ Read, Cursor, Chain, Take };
+use std::net::{ SocketAddr, TcpStream };
+use std::sync::{ Arc, Mutex };
+use std::time::Instant;
+use crate::{ Method, HeaderMap };
+
+/// A parsed incoming request. Middleware and handlers get this
+/// instead of having to split the raw request string themselves
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method:Method,
+    /// The requested path, without the query string
+    pub path:String,
+    /// The query string, without the leading `?`
+    pub query:String,
+    pub headers:HeaderMap,
+    /// The request exactly like it was read from the stream
+    pub raw:String,
+    /// The uri-variables, like `id` when connecting to `test/:id`
+    pub params:HashMap<String, String>,
+    pub peer:Option<SocketAddr>,
+    /// When the request was read, useful for timing
+    pub received:Instant,
+    /*- Bodies that are read by the handler (multipart uploads) are left
+        on the stream instead of being read into `raw`. Shared by clones -*/
+    pub(crate) body_stream:Arc<Mutex<Option<BodyStream>>>,
+    /*- The body exactly like it was read, `raw` has invalid utf-8 replaced -*/
+    pub(crate) body_bytes:Option<Arc<Vec<u8>>>,
+    /*- The keys of signed and private cookies, from `ServerOptions` -*/
+    #[cfg(feature = "secure-cookies")]
+    pub(crate) cookie_keys:Option<crate::CookieKeys>,
+    /*- From `ServerOptions::sessions` -*/
+    pub(crate) session:Option<crate::Session>,
+}
+
+/*- What was read past the head already, followed by the rest of the body on the stream -*/
+pub(crate) type BodyStream = Chain<Cursor<Vec<u8>>, Take<TcpStream>>;
+
+impl Request {
+
+    /// Parse the request line of a raw request
+    pub fn parse(raw:String, peer:Option<SocketAddr>) -> Request {
+        let mut request_line = raw.split('\n').next().unwrap_or("").trim().split(' ');
+
+        /*- Get the method and path from the request line -*/
+        let method = Method::parse(request_line.next().unwrap_or(""));
+        let target = request_line.next().unwrap_or("/");
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, query),
+            None => (target, ""),
+        };
+
+        Request {
+            method,
+            path: path.to_string(),
+            query: query.to_string(),
+            headers: HeaderMap::parse(&raw),
+            params: HashMap::new(),
+            raw,
+            peer,
+            received: Instant::now(),
+            body_stream: Arc::new(Mutex::new(None)),
+            body_bytes: None,
+            #[cfg(feature = "secure-cookies")]
+            cookie_keys: None,
+            session: None,
+        }
+    }
+
+    /*- Leave the body on the stream, `read` is what was read past the head already -*/
+    pub(crate) fn stream_body(&mut self, read:Vec<u8>, stream:TcpStream, remaining:u64) {
+        self.body_stream = Arc::new(Mutex::new(Some(Cursor::new(read).chain(stream.take(remaining)))));
+    }
+
+    /// A reader for the body. Bodies left on the stream (like multipart uploads) can
+    /// only be read once, and `body()` is empty for them; other bodies are read from memory
+    pub fn body_reader(&self) -> Box<dyn Read + Send> {
+        let streamed = self.body_stream.lock().unwrap_or_else(|e| e.into_inner()).take();
+
+        match streamed {
+            Some(stream) => Box::new(stream),
+            None => Box::new(Cursor::new(self.body_bytes().to_vec())),
+        }
+    }
+
+    /// Get the (first) value of a header, case-insensitive. See `headers` for the others
+    pub fn header(&self, name:&str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The first line of the request, like `GET /path HTTP/1.1`
+    pub fn request_line(&self) -> &str {
+        self.raw.split('\n').next().unwrap_or("").trim()
+    }
+
+    /// Everything before the body
+    pub fn head(&self) -> &str {
+        match self.raw.split_once("\r\n\r\n") {
+            Some((head, _)) => head,
+            None => &self.raw,
+        }
+    }
+
+    /// The body as bytes, without invalid utf-8 being replaced like in `body()`
+    pub fn body_bytes(&self) -> &[u8] {
+        match &self.body_bytes {
+            Some(bytes) => bytes,
+            None => self.body().as_bytes(),
+        }
+    }
+
+    /// Everything after the headers
+    pub fn body(&self) -> &str {
+        match self.raw.split_once("\r\n\r\n") {
+            Some((_, body)) => body,
+            None => "",
+        }
+    }
+}
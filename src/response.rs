@@ -0,0 +1,159 @@
+/*- Imports -*/
+use std::io::Write;
+use std::time::SystemTime;
+use crate::utils::{ ResponseType, status_message, http_date };
+use crate::compression::add_vary;
+
+/// A http response which hasn't been written to the stream yet. Everything
+/// the server sends by itself (files, 404 pages) goes through this, which
+/// lets middleware modify it before it leaves.
+/// # Examples
+/// ```ignore
+/// Response::content(200, ResponseType::Json, "{\"ok\":true}")
+///     .header("X-Powered-By", "fastserve")
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status:u16,
+    pub headers:Vec<(String, String)>,
+    pub body:Vec<u8>,
+}
+
+impl Response {
+
+    /// A response with only a status. The body will be
+    /// the status code and message, like `404 Not Found`
+    pub fn new(status:u16) -> Response {
+        Response::content(status, ResponseType::Text, error_body(status, &ResponseType::Text))
+    }
+
+    /// A response describing an error status, with the body
+    /// formatted as json, html or (for anything else) text
+    pub fn error(status:u16, response_type:&ResponseType) -> Response {
+        match response_type {
+            ResponseType::Json | ResponseType::Html => Response::content(status, response_type.clone(), error_body(status, response_type)),
+            _ => Response::new(status),
+        }
+    }
+
+    /// A response with a body and a content type
+    pub fn content<T:Into<Vec<u8>>>(status:u16, response_type:ResponseType, content:T) -> Response {
+        Response {
+            status,
+            headers: vec![(String::from("Content-Type"), response_type.content_type().to_string())],
+            body: content.into(),
+        }
+    }
+
+    /// Add a header, builder style. Use `set_header` to replace one
+    pub fn header(mut self, key:&str, value:&str) -> Response {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Replace all headers named `key` (case-insensitive) with a single value
+    pub fn set_header(&mut self, key:&str, value:&str) {
+        self.remove_header(key);
+        self.headers.push((key.to_string(), value.to_string()));
+    }
+
+    /// Remove all headers named `key` (case-insensitive)
+    pub fn remove_header(&mut self, key:&str) {
+        self.headers.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+    }
+
+    /// Get the first header named `key` (case-insensitive)
+    pub fn get_header(&self, key:&str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /*- An error without a body of its own, like the ones `new` and `error` make. Those
+        all contain the status message, so other bodies are ruled out without formatting -*/
+    pub(crate) fn is_plain_error(&self) -> bool {
+        if self.status < 400 { return false; };
+        if self.body.is_empty() { return true; };
+
+        let body = match std::str::from_utf8(&self.body) {
+            Ok(body) if body.contains(status_message(self.status)) => body,
+            _ => return false,
+        };
+
+        [ResponseType::Text, ResponseType::Json, ResponseType::Html].iter()
+            .any(|response_type| error_body(self.status, response_type) == body)
+    }
+
+    /// Set the `Cache-Control` header, takes a string or a `CacheControl`
+    pub fn cache_control<T:Into<String>>(mut self, cache_control:T) -> Response {
+        self.set_header("Cache-Control", &cache_control.into());
+        self
+    }
+
+    /// Set the `Expires` header
+    pub fn expires(mut self, time:SystemTime) -> Response {
+        self.set_header("Expires", &http_date(time));
+        self
+    }
+
+    /// Add a header name to `Vary`, telling caches that the response depends on it
+    pub fn vary(mut self, header:&str) -> Response {
+        add_vary(&mut self, header);
+        self
+    }
+
+    /// Write the status line, headers and body to the stream
+    pub fn write_to<W:Write>(&self, stream:&mut W) {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, status_message(self.status));
+
+        /*- Content-Length is always computed from the body -*/
+        for (key, value) in self.headers.iter() {
+            if key.eq_ignore_ascii_case("Content-Length") { continue; };
+            head.push_str(&format!("{}: {}\r\n", key, value));
+        };
+        /*- 1xx, 204 and 304 responses never have a body, nor a length -*/
+        if !(self.status < 200 || self.status == 204 || self.status == 304) {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        };
+        head.push_str("\r\n");
+
+        /*- A closed connection isn't something we can do anything about -*/
+        stream.write_all(head.as_bytes()).unwrap_or(());
+        stream.write_all(&self.body).unwrap_or(());
+        stream.flush().unwrap_or(());
+    }
+}
+
+/*- The body `new` and `error` give a status, json, html or text -*/
+fn error_body(status:u16, response_type:&ResponseType) -> String {
+    let message = status_message(status);
+
+    match response_type {
+        ResponseType::Json => format!("{{\"status\":{},\"message\":\"{}\"}}", status, message),
+        ResponseType::Html => format!("<!DOCTYPE html><html><head><title>{0} {1}</title></head><body><h1>{0} {1}</h1></body></html>", status, message),
+        _ => format!("{} {}", status, message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_errors_are_told_apart_from_custom_bodies() {
+        assert!(Response::new(404).is_plain_error());
+        assert!(Response::error(500, &ResponseType::Json).is_plain_error());
+        assert!(Response::error(401, &ResponseType::Html).is_plain_error());
+        assert!(Response::content(403, ResponseType::Text, "").is_plain_error());
+
+        assert!(!Response::new(200).is_plain_error());
+        assert!(!Response::content(404, ResponseType::Text, "no user named \"Not Found\"").is_plain_error());
+        assert!(!Response::content(404, ResponseType::Json, "{\"error\":\"Not Found\"}").is_plain_error());
+        assert!(!Response::content(500, ResponseType::Binary, vec![0xFF, 0xFE]).is_plain_error());
+
+        /*- The body of another status is a body of its own -*/
+        let mut response = Response::new(404);
+        response.status = 410;
+        assert!(!response.is_plain_error());
+    }
+}
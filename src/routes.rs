@@ -0,0 +1,160 @@
+/*- Imports -*/
+use std::fmt;
+use termcolor::Color;
+use crate::{ RouteRoot, RouteValue, Method, utils::log };
+
+/// What kind of value an endpoint resolves to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteKind {
+    File,
+    Function,
+    Handler,
+    None,
+}
+
+/// A single endpoint of the route tree, with its stacks resolved into one full path
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteEntry {
+    /// The required http method, `None` means that any method will match (files)
+    pub method:Option<Method>,
+    pub path:String,
+    pub kind:RouteKind,
+    /// The file that will be served, only set for `RouteKind::File`
+    pub target:Option<&'static str>,
+}
+
+/// Flatten the route tree into a list of every endpoint, depth first in the order
+/// they are declared. That isn't always the order they win in: the first matching
+/// endpoint of a list wins, but a match inside of a stack is replaced by a later
+/// match in the list the stack is in
+/// # Example
+/// ```ignore
+/// for entry in list_routes(&routes) {
+///     println!("{}", entry);
+/// }
+/// ```
+pub fn list_routes(routes:&[RouteRoot]) -> Vec<RouteEntry> {
+    let mut entries:Vec<RouteEntry> = Vec::new();
+    collect_routes(routes, "", &mut entries);
+
+    entries
+}
+
+/*- Walk down the stacks and push every endpoint we find -*/
+fn collect_routes(routes:&[RouteRoot], path_iter:&str, entries:&mut Vec<RouteEntry>) {
+    for route in routes.iter() {
+        match route {
+            RouteRoot::Stack(path, routes) | RouteRoot::StackWith(path, _, routes) => {
+                collect_routes(routes, &join_path(path_iter, path), entries);
+            },
+            RouteRoot::Endpoint(endpoint_name, value) => {
+                let (method, kind, target) = match value {
+                    RouteValue::File(file_path) => (None, RouteKind::File, Some(*file_path)),
+                    RouteValue::Function((method, _)) => (Some(*method), RouteKind::Function, None),
+                    RouteValue::Handler((method, _)) => (Some(*method), RouteKind::Handler, None),
+                    RouteValue::None => (None, RouteKind::None, None),
+                };
+
+                entries.push(RouteEntry {
+                    method,
+                    path: join_path(path_iter, endpoint_name),
+                    kind,
+                    target,
+                });
+            },
+        };
+    };
+}
+
+/*- Join a stack path and a stack or endpoint name with one slash between them,
+    so that trailing and leading slashes don't give double slashes ("//") -*/
+pub(crate) fn join_path(path_iter:&str, endpoint_name:&str) -> String {
+    format!("{}/{}", path_iter.trim_end_matches('/'), endpoint_name.trim_start_matches('/'))
+}
+
+/// Print the flattened route table using the colored `log` function
+pub fn log_routes(routes:&[RouteRoot]) {
+    let entries = list_routes(routes);
+
+    /*- Pad the paths so that the targets line up -*/
+    let width = entries.iter().map(|e| e.path.len()).max().unwrap_or(0);
+
+    log(Color::Rgb(255, 255, 0), &format!("Routes ({}):", entries.len()));
+    for entry in entries.iter() {
+        log(Color::Rgb(171, 178, 191), &format!("  {:width$}", entry, width = width));
+    }
+}
+
+impl fmt::Display for RouteKind {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RouteKind::File => "file",
+            RouteKind::Function => "function",
+            RouteKind::Handler => "handler",
+            RouteKind::None => "none",
+        })
+    }
+}
+
+/*- Formats like `GET    /api/v2/some_endpoint  function`. The
+    width (if any) is used to pad the path column -*/
+impl fmt::Display for RouteEntry {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        let method = match self.method {
+            Some(method) => method.to_string(),
+            None => String::from("ANY"),
+        };
+        let width = f.width().unwrap_or(0);
+
+        write!(f, "{:<6} {:<width$}  {}", method, self.path, self.kind, width = width)?;
+        if let Some(target) = self.target {
+            write!(f, " -> {}", target)?;
+        };
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ Request, Response, StackOptions };
+
+    fn handler(_request:&Request) -> Response { Response::new(200) }
+
+    #[test]
+    fn join_path_avoids_double_slashes() {
+        assert_eq!(join_path("", "index"), "/index");
+        assert_eq!(join_path("/api", "users"), "/api/users");
+        assert_eq!(join_path("/api/", "users"), "/api/users");
+        assert_eq!(join_path("/api//", "/users"), "/api/users");
+        assert_eq!(join_path("/api", ""), "/api/");
+        assert_eq!(join_path("/", ""), "/");
+    }
+
+    #[test]
+    fn nested_stacks_are_flattened_in_declaration_order() {
+        let routes = vec![
+            RouteRoot::Endpoint("", RouteValue::File("index.html")),
+            RouteRoot::Stack("/api", vec![
+                RouteRoot::Endpoint("health", RouteValue::Handler((Method::Get, handler))),
+                RouteRoot::StackWith("/v2/", StackOptions::default(), vec![
+                    RouteRoot::Endpoint("users", RouteValue::Handler((Method::Post, handler))),
+                    RouteRoot::Stack("/admin", vec![
+                        RouteRoot::Endpoint("stats", RouteValue::None),
+                    ]),
+                ]),
+            ]),
+        ];
+
+        let entries = list_routes(&routes);
+        let paths:Vec<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, vec!["/", "/api/health", "/api/v2/users", "/api/v2/admin/stats"]);
+
+        assert_eq!(entries[0], RouteEntry { method: None, path: String::from("/"), kind: RouteKind::File, target: Some("index.html") });
+        assert_eq!(entries[2].method, Some(Method::Post));
+        assert_eq!(entries[2].kind, RouteKind::Handler);
+        assert_eq!(entries[3].kind, RouteKind::None);
+        assert_eq!(entries[2].to_string(), "POST   /api/v2/users  handler");
+    }
+}
@@ -0,0 +1,72 @@
+/*- Imports -*/
+use crate::{ Middleware, Request, ResponseType };
+
+/// Decides if a request is allowed into a stack, returning `false` will respond with 401
+pub type AuthFunction = fn(&Request) -> bool;
+
+/// Options for a `RouteRoot::StackWith`. Every endpoint inside of the stack
+/// (and its sub-stacks) inherits them. Options that can only have one value
+/// (like `max_body`) are overridden by nested stacks, while middleware and
+/// auth requirements add up.
+/// # Examples
+/// ```ignore
+/// RR::StackWith("/api", StackOptions {
+///     content_type: Some(ResponseType::Json),
+///     max_body: Some(64 * 1024),
+///     ..Default::default()
+/// }, vec![ ... ])
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StackOptions {
+    /// Layers that run after the server wide middleware,
+    /// for requests to endpoints inside of the stack
+    pub middleware:Vec<Middleware>,
+    /// All of these have to pass, otherwise the request gets a 401
+    pub auth:Vec<AuthFunction>,
+    /// Requests with a larger `Content-Length` (in bytes) get a 413
+    pub max_body:Option<usize>,
+    /// Requests with a larger head (request line and headers, in bytes) get a 431.
+    /// Like `max_headers`, this can only be lower than `ServerOptions::limits`
+    pub max_header_size:Option<usize>,
+    /// Requests with more headers get a 431
+    pub max_headers:Option<usize>,
+    /// Used for error responses, and responses without a `Content-Type`
+    pub content_type:Option<ResponseType>,
+    /// A file in the static dir which is served (with the real status)
+    /// for errors inside of the stack, like 404 or 401
+    pub error_page:Option<&'static str>,
+}
+
+/// The options of every stack a request went through, merged together
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Scope {
+    pub middleware:Vec<Middleware>,
+    pub auth:Vec<AuthFunction>,
+    pub max_body:Option<usize>,
+    pub max_header_size:Option<usize>,
+    pub max_headers:Option<usize>,
+    pub content_type:Option<ResponseType>,
+    pub error_page:Option<&'static str>,
+}
+
+impl Scope {
+
+    /// Merge the options, outermost stack first
+    pub fn resolve(chain:&[StackOptions]) -> Scope {
+        let mut scope = Scope::default();
+
+        for options in chain.iter() {
+            scope.middleware.extend(options.middleware.iter().copied());
+            scope.auth.extend(options.auth.iter().copied());
+
+            /*- Inner stacks override the outer ones -*/
+            if options.max_body.is_some() { scope.max_body = options.max_body; };
+            if options.max_header_size.is_some() { scope.max_header_size = options.max_header_size; };
+            if options.max_headers.is_some() { scope.max_headers = options.max_headers; };
+            if options.content_type.is_some() { scope.content_type = options.content_type.clone(); };
+            if options.error_page.is_some() { scope.error_page = options.error_page; };
+        };
+
+        scope
+    }
+}
@@ -0,0 +1,312 @@
+/*- Imports -*/
+use aes_gcm::{ Aes256Gcm, KeyInit, Nonce, aead::{ Aead, Payload } };
+use hmac::{ Hmac, Mac };
+use sha2::Sha256;
+use std::sync::Arc;
+use crate::{ Cookie, CookieJar, Request, Response };
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The keys signed and private cookies are made with, derived from server secrets.
+/// The first secret signs and encrypts new cookies, the others are only used to
+/// read cookies made before, so that secrets can be rotated without logging everyone out
+/// # Examples
+/// ```ignore
+/// cookie_keys: Some(CookieKeys::new(&[
+///     &env::var("COOKIE_SECRET").unwrap(),
+///     &env::var("OLD_COOKIE_SECRET").unwrap(),
+/// ])),
+/// ```
+#[derive(Clone)]
+pub struct CookieKeys {
+    /*- Cloned into every request -*/
+    keys:Arc<Vec<Key>>,
+}
+
+struct Key {
+    signing:[u8; 32],
+    encryption:[u8; 32],
+}
+
+/*- Never print the keys -*/
+impl std::fmt::Debug for CookieKeys {
+    fn fmt(&self, f:&mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "CookieKeys({} keys)", self.keys.len())
+    }
+}
+
+impl CookieKeys {
+
+    /// Keys from secret strings, newest first. Secrets should be long
+    /// and random, like 32 bytes from a password generator.
+    /// Panics without any secrets, so that it fails when the options are made
+    pub fn new(secrets:&[&str]) -> CookieKeys {
+        let secrets:Vec<&[u8]> = secrets.iter().map(|secret| secret.as_bytes()).collect();
+        CookieKeys::from_secrets(&secrets)
+    }
+
+    /// Like `new`, from raw bytes
+    pub fn from_secrets(secrets:&[&[u8]]) -> CookieKeys {
+        assert!(!secrets.is_empty(), "CookieKeys needs at least one secret");
+        if secrets.iter().any(|secret| secret.len() < 32) {
+            tracing::warn!("a cookie secret is shorter than 32 bytes");
+        };
+
+        /*- Different keys for signing and encrypting, so that one can't be used for the other -*/
+        let derive = |secret:&[u8], purpose:&[u8]| -> [u8; 32] {
+            let mut mac = <HmacSha256 as Mac>::new_from_slice(secret).expect("hmac takes keys of any size");
+            mac.update(purpose);
+            mac.finalize().into_bytes().into()
+        };
+
+        CookieKeys {
+            keys: secrets.iter().map(|secret| Key {
+                signing: derive(secret, b"fastserve signed cookie"),
+                encryption: derive(secret, b"fastserve private cookie"),
+            }).collect::<Vec<Key>>().into(),
+        }
+    }
+
+    /// A random key, for development. Cookies made with it stop working when the
+    /// server restarts, and aren't accepted by other instances of the server
+    pub fn random() -> CookieKeys {
+        CookieKeys::from_secrets(&[&rand::random::<[u8; 32]>()])
+    }
+
+    /// Signed cookies, readable by the browser but not changeable
+    pub fn signed<'a>(&'a self, jar:&'a CookieJar) -> SignedJar<'a> {
+        SignedJar { keys: self, jar }
+    }
+
+    /// Encrypted cookies, neither readable nor changeable by the browser
+    pub fn private<'a>(&'a self, jar:&'a CookieJar) -> PrivateJar<'a> {
+        PrivateJar { keys: self, jar }
+    }
+
+    /*- The constructors make sure there is one -*/
+    fn current(&self) -> &Key {
+        &self.keys[0]
+    }
+}
+
+/// A view of a `CookieJar` that only returns cookies which were signed with
+/// the server keys. Values are base64 encoded, so anything can be stored
+pub struct SignedJar<'a> {
+    keys:&'a CookieKeys,
+    jar:&'a CookieJar,
+}
+
+impl SignedJar<'_> {
+
+    /// The value of a signed cookie, `None` if it's missing or was tampered with
+    pub fn get(&self, name:&str) -> Option<String> {
+        self.jar.get_all(name).into_iter().find_map(|value| {
+            let (signature, encoded) = value.split_once('.')?;
+            let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+
+            /*- Any of the keys, so that cookies signed before a rotation still work -*/
+            let valid = self.keys.keys.iter().any(|key| mac(&key.signing, name, encoded).verify_slice(&signature).is_ok());
+            if !valid { return None; };
+
+            String::from_utf8(base64::decode_config(encoded, base64::URL_SAFE_NO_PAD).ok()?).ok()
+        })
+    }
+
+    /// Sign a cookie, to send it with `Response::cookie`
+    pub fn sign(&self, mut cookie:Cookie) -> Cookie {
+        let encoded = base64::encode_config(cookie.value.as_bytes(), base64::URL_SAFE_NO_PAD);
+        let signature = mac(&self.keys.current().signing, &cookie.name, &encoded).finalize().into_bytes();
+
+        cookie.value = format!("{}.{}", base64::encode_config(signature, base64::URL_SAFE_NO_PAD), encoded);
+        cookie
+    }
+}
+
+/*- The name is signed too, so that a value can't be moved to another cookie -*/
+fn mac(key:&[u8], name:&str, value:&str) -> HmacSha256 {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("hmac takes keys of any size");
+    mac.update(name.as_bytes());
+    mac.update(b"=");
+    mac.update(value.as_bytes());
+    mac
+}
+
+/// A view of a `CookieJar` that decrypts cookies which were encrypted with the server keys
+pub struct PrivateJar<'a> {
+    keys:&'a CookieKeys,
+    jar:&'a CookieJar,
+}
+
+impl PrivateJar<'_> {
+
+    /// The decrypted value of a private cookie, `None` if it's missing or was tampered with
+    pub fn get(&self, name:&str) -> Option<String> {
+        self.jar.get_all(name).into_iter().find_map(|value| {
+            let data = base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()?;
+            if data.len() < 12 { return None; };
+            let (nonce, ciphertext) = data.split_at(12);
+
+            self.keys.keys.iter().find_map(|key| {
+                let plaintext = Aes256Gcm::new((&key.encryption).into())
+                    .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: name.as_bytes() })
+                    .ok()?;
+                String::from_utf8(plaintext).ok()
+            })
+        })
+    }
+
+    /// Encrypt a cookie, to send it with `Response::cookie`
+    pub fn encrypt(&self, mut cookie:Cookie) -> Cookie {
+        let nonce = rand::random::<[u8; 12]>();
+        let ciphertext = Aes256Gcm::new((&self.keys.current().encryption).into())
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: cookie.value.as_bytes(), aad: cookie.name.as_bytes() })
+            .expect("aes-gcm encrypts any cookie sized value");
+
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&ciphertext);
+        cookie.value = base64::encode_config(data, base64::URL_SAFE_NO_PAD);
+        cookie
+    }
+}
+
+impl Request {
+
+    /*- The server keys. Without them nothing can be signed, and it's
+        better to fail than to sign with a key that changes on restart -*/
+    fn keys(&self) -> Result<&CookieKeys, Response> {
+        self.cookie_keys.as_ref().ok_or_else(|| {
+            tracing::error!("signed or private cookies are used, but ServerOptions::cookie_keys isn't set");
+            Response::new(500)
+        })
+    }
+
+    /// The value of a signed cookie, `None` if it's missing or was tampered with
+    /// (or if `ServerOptions::cookie_keys` isn't set)
+    /// # Examples
+    /// ```ignore
+    /// let user_id = request.signed_cookie("user");
+    /// match request.sign_cookie(Cookie::new("user", "42").http_only()) {
+    ///     Ok(cookie) => Response::new(200).cookie(cookie),
+    ///     Err(response) => response,
+    /// }
+    /// ```
+    pub fn signed_cookie(&self, name:&str) -> Option<String> {
+        self.keys().ok()?.signed(&self.cookies()).get(name)
+    }
+
+    /// Sign a cookie with the server keys. Fails with a `500` to send back
+    /// if `ServerOptions::cookie_keys` isn't set
+    pub fn sign_cookie(&self, cookie:Cookie) -> Result<Cookie, Response> {
+        Ok(self.keys()?.signed(&CookieJar::default()).sign(cookie))
+    }
+
+    /// The decrypted value of a private cookie, `None` if it's missing or was tampered with
+    /// (or if `ServerOptions::cookie_keys` isn't set)
+    pub fn private_cookie(&self, name:&str) -> Option<String> {
+        self.keys().ok()?.private(&self.cookies()).get(name)
+    }
+
+    /// Encrypt a cookie with the server keys. Fails with a `500` to send back
+    /// if `ServerOptions::cookie_keys` isn't set
+    pub fn encrypt_cookie(&self, cookie:Cookie) -> Result<Cookie, Response> {
+        Ok(self.keys()?.private(&CookieJar::default()).encrypt(cookie))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET:&str = "0123456789abcdef0123456789abcdef";
+    const OLD_SECRET:&str = "fedcba9876543210fedcba9876543210";
+
+    /*- A jar like the browser would send back, from a cookie the server set -*/
+    fn sent_back(cookie:&Cookie) -> CookieJar {
+        CookieJar::parse(&format!("{}={}", cookie.name, cookie.value))
+    }
+
+    #[test]
+    fn signed_round_trip() {
+        let keys = CookieKeys::new(&[SECRET]);
+        let cookie = keys.signed(&CookieJar::default()).sign(Cookie::new("user", "42; = ü"));
+        assert!(!cookie.value.contains(';'));
+
+        assert_eq!(keys.signed(&sent_back(&cookie)).get("user").as_deref(), Some("42; = ü"));
+        assert_eq!(keys.signed(&sent_back(&cookie)).get("other"), None);
+    }
+
+    #[test]
+    fn private_round_trip() {
+        let keys = CookieKeys::new(&[SECRET]);
+        let cookie = keys.private(&CookieJar::default()).encrypt(Cookie::new("cart", "3 apples"));
+        assert!(!cookie.value.contains("apples"));
+        assert_eq!(keys.private(&sent_back(&cookie)).get("cart").as_deref(), Some("3 apples"));
+
+        /*- Random nonces, the same value encrypts differently every time -*/
+        let again = keys.private(&CookieJar::default()).encrypt(Cookie::new("cart", "3 apples"));
+        assert_ne!(cookie.value, again.value);
+    }
+
+    #[test]
+    fn tampering_is_rejected() {
+        let keys = CookieKeys::new(&[SECRET]);
+
+        let signed = keys.signed(&CookieJar::default()).sign(Cookie::new("user", "42"));
+        let (signature, _) = signed.value.split_once('.').unwrap();
+        let forged = format!("user={}.{}", signature, base64::encode_config("1", base64::URL_SAFE_NO_PAD));
+        assert_eq!(keys.signed(&CookieJar::parse(&forged)).get("user"), None);
+        assert_eq!(keys.signed(&CookieJar::parse("user=42")).get("user"), None);
+        assert_eq!(keys.signed(&CookieJar::parse("user=.NDI")).get("user"), None);
+
+        let private = keys.private(&CookieJar::default()).encrypt(Cookie::new("cart", "3 apples"));
+        let mut bytes = base64::decode_config(&private.value, base64::URL_SAFE_NO_PAD).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let flipped = format!("cart={}", base64::encode_config(bytes, base64::URL_SAFE_NO_PAD));
+        assert_eq!(keys.private(&CookieJar::parse(&flipped)).get("cart"), None);
+        assert_eq!(keys.private(&CookieJar::parse("cart=short")).get("cart"), None);
+
+        /*- Other keys can't read them either -*/
+        let other = CookieKeys::new(&[OLD_SECRET]);
+        assert_eq!(other.signed(&sent_back(&signed)).get("user"), None);
+        assert_eq!(other.private(&sent_back(&private)).get("cart"), None);
+    }
+
+    #[test]
+    fn values_can_not_move_to_another_name() {
+        let keys = CookieKeys::new(&[SECRET]);
+
+        let signed = keys.signed(&CookieJar::default()).sign(Cookie::new("user", "42"));
+        let moved = CookieJar::parse(&format!("admin={}", signed.value));
+        assert_eq!(keys.signed(&moved).get("admin"), None);
+
+        let private = keys.private(&CookieJar::default()).encrypt(Cookie::new("user", "42"));
+        let moved = CookieJar::parse(&format!("admin={}", private.value));
+        assert_eq!(keys.private(&moved).get("admin"), None);
+    }
+
+    #[test]
+    fn rotated_keys() {
+        let old = CookieKeys::new(&[OLD_SECRET]);
+        let signed = old.signed(&CookieJar::default()).sign(Cookie::new("user", "42"));
+        let private = old.private(&CookieJar::default()).encrypt(Cookie::new("cart", "3 apples"));
+
+        /*- Still read after a rotation, and new cookies use the new secret -*/
+        let rotated = CookieKeys::new(&[SECRET, OLD_SECRET]);
+        assert_eq!(rotated.signed(&sent_back(&signed)).get("user").as_deref(), Some("42"));
+        assert_eq!(rotated.private(&sent_back(&private)).get("cart").as_deref(), Some("3 apples"));
+        let resigned = rotated.signed(&CookieJar::default()).sign(Cookie::new("user", "42"));
+        assert_eq!(CookieKeys::new(&[SECRET]).signed(&sent_back(&resigned)).get("user").as_deref(), Some("42"));
+
+        /*- And rejected once the old secret is rotated out -*/
+        let new = CookieKeys::new(&[SECRET]);
+        assert_eq!(new.signed(&sent_back(&signed)).get("user"), None);
+        assert_eq!(new.private(&sent_back(&private)).get("cart"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one secret")]
+    fn empty_key_sets_are_rejected() {
+        CookieKeys::new(&[]);
+    }
+}
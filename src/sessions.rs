@@ -0,0 +1,621 @@
+/*- Imports -*/
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{ Arc, Mutex, MutexGuard };
+use std::thread;
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+use percent_encoding::{ utf8_percent_encode, percent_decode_str, AsciiSet, CONTROLS };
+use crate::{ Cookie, Request, Response, SameSite };
+
+/*- How often expired sessions are removed from the store -*/
+const CLEANUP_INTERVAL:Duration = Duration::from_secs(5 * 60);
+
+/// What is saved for a session
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionData {
+    pub values:HashMap<String, String>,
+    pub created:SystemTime,
+    /// When the session was last used by a request
+    pub accessed:SystemTime,
+}
+
+impl SessionData {
+    pub fn new() -> SessionData {
+        let now = SystemTime::now();
+        SessionData { values: HashMap::new(), created: now, accessed: now }
+    }
+}
+
+impl Default for SessionData {
+    fn default() -> SessionData { SessionData::new() }
+}
+
+/// Where sessions are kept. Implement this for custom backends, like a database.
+/// Ids are random and url safe, so they can be used as keys or file names as they are
+pub trait SessionStore: Send + Sync {
+    fn load(&self, id:&str) -> Option<SessionData>;
+    fn save(&self, id:&str, data:&SessionData) -> io::Result<()>;
+    fn remove(&self, id:&str) -> io::Result<()>;
+
+    /// Remove every session that `expired` returns true for. The server calls
+    /// this every few minutes on a thread of its own
+    fn cleanup(&self, expired:&dyn Fn(&SessionData) -> bool);
+}
+
+/// Keeps sessions in memory. They're lost when the server restarts
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    sessions:Arc<Mutex<HashMap<String, SessionData>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore { MemoryStore::default() }
+
+    /*- A panic while holding the lock doesn't leave the map broken -*/
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, SessionData>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn len(&self) -> usize { self.lock().len() }
+    pub fn is_empty(&self) -> bool { self.lock().is_empty() }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id:&str) -> Option<SessionData> {
+        self.lock().get(id).cloned()
+    }
+
+    fn save(&self, id:&str, data:&SessionData) -> io::Result<()> {
+        self.lock().insert(id.to_string(), data.clone());
+        Ok(())
+    }
+
+    fn remove(&self, id:&str) -> io::Result<()> {
+        self.lock().remove(id);
+        Ok(())
+    }
+
+    fn cleanup(&self, expired:&dyn Fn(&SessionData) -> bool) {
+        self.lock().retain(|_, data| !expired(data));
+    }
+}
+
+/*- Keys and values are written one pair per line, separated by = -*/
+const ESCAPE:&AsciiSet = &CONTROLS.add(b'%').add(b'=');
+
+/// Keeps every session in a file of its own, named by the session id, so
+/// sessions survive restarts. The directory shouldn't be readable by others
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    pub dir:PathBuf,
+}
+
+impl FileStore {
+
+    /// A store in `dir`, which is created if it doesn't exist
+    pub fn new<T:Into<PathBuf>>(dir:T) -> io::Result<FileStore> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileStore { dir })
+    }
+
+    fn parse(content:&str) -> Option<SessionData> {
+        let mut lines = content.lines();
+        let (created, accessed) = lines.next()?.split_once(' ')?;
+        let mut data = SessionData {
+            values: HashMap::new(),
+            created: UNIX_EPOCH + Duration::from_secs(created.parse().ok()?),
+            accessed: UNIX_EPOCH + Duration::from_secs(accessed.parse().ok()?),
+        };
+
+        for line in lines {
+            let (key, value) = line.split_once('=')?;
+            data.values.insert(
+                percent_decode_str(key).decode_utf8_lossy().to_string(),
+                percent_decode_str(value).decode_utf8_lossy().to_string(),
+            );
+        };
+
+        Some(data)
+    }
+
+    fn format(data:&SessionData) -> String {
+        let secs = |time:SystemTime| time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let mut content = format!("{} {}\n", secs(data.created), secs(data.accessed));
+        for (key, value) in data.values.iter() {
+            content.push_str(&format!("{}={}\n", utf8_percent_encode(key, ESCAPE), utf8_percent_encode(value, ESCAPE)));
+        };
+
+        content
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id:&str) -> Option<SessionData> {
+        FileStore::parse(&fs::read_to_string(self.dir.join(id)).ok()?)
+    }
+
+    /*- Written next to the session and renamed over it, so a request never
+        reads a half written session. The temp name is unique to this save,
+        so that two requests saving the same session don't write into one file -*/
+    fn save(&self, id:&str, data:&SessionData) -> io::Result<()> {
+        let temp = self.dir.join(format!(".{}.{:016x}.tmp", id, rand::random::<u64>()));
+        if let Err(error) = fs::write(&temp, FileStore::format(data)) {
+            fs::remove_file(&temp).unwrap_or(());
+            return Err(error);
+        };
+        fs::rename(&temp, self.dir.join(id))
+    }
+
+    fn remove(&self, id:&str) -> io::Result<()> {
+        match fs::remove_file(self.dir.join(id)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    fn cleanup(&self, expired:&dyn Fn(&SessionData) -> bool) {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(error) => return tracing::error!(%error, "couldn't read the session directory"),
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !valid_id(&name) { continue; };
+
+            if self.load(&name).map(|data| expired(&data)).unwrap_or(false) {
+                self.remove(&name).unwrap_or(());
+            };
+        };
+    }
+}
+
+/// Server side sessions, set through `ServerOptions::sessions`. A random session id
+/// is sent as a cookie, and the values of the session are kept in the store.
+///
+/// Sessions are only created once something is set, and are saved after the
+/// handler (and the after middleware) has run. `RouteValue::Function`
+/// endpoints write straight to the stream, so their sessions aren't saved.
+/// # Examples
+/// ```ignore
+/// sessions: Some(Sessions::new(FileStore::new("sessions").unwrap())
+///     .idle_timeout(Duration::from_secs(30 * 60))
+///     .absolute_timeout(Duration::from_secs(7 * 24 * 60 * 60))),
+/// ```
+#[derive(Clone)]
+pub struct Sessions {
+    pub store:Arc<dyn SessionStore>,
+    /// The session cookie, its value is replaced by the session id.
+    /// Defaults to `session`, with `Path=/`, `HttpOnly` and `SameSite=Lax`
+    pub cookie:Cookie,
+    /// Sessions that haven't been used for this long expire
+    pub idle_timeout:Option<Duration>,
+    /// Sessions expire this long after they were created, even if they're in use
+    pub absolute_timeout:Option<Duration>,
+    last_cleanup:Arc<Mutex<Instant>>,
+}
+
+impl fmt::Debug for Sessions {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sessions")
+            .field("cookie", &self.cookie)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("absolute_timeout", &self.absolute_timeout)
+            .finish()
+    }
+}
+
+impl Default for Sessions {
+
+    /// Sessions in memory, which expire after 30 minutes of not being
+    /// used, or a day after they were created
+    fn default() -> Sessions {
+        Sessions::new(MemoryStore::new())
+    }
+}
+
+impl Sessions {
+    pub fn new<T:SessionStore + 'static>(store:T) -> Sessions {
+        Sessions {
+            store: Arc::new(store),
+            cookie: Cookie::new("session", "").path("/").http_only().same_site(SameSite::Lax),
+            idle_timeout: Some(Duration::from_secs(30 * 60)),
+            absolute_timeout: Some(Duration::from_secs(24 * 60 * 60)),
+            last_cleanup: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn cookie(mut self, cookie:Cookie) -> Sessions { self.cookie = cookie; self }
+    pub fn idle_timeout(mut self, timeout:Duration) -> Sessions { self.idle_timeout = Some(timeout); self }
+    pub fn absolute_timeout(mut self, timeout:Duration) -> Sessions { self.absolute_timeout = Some(timeout); self }
+
+    /// Whether a session has expired
+    pub fn expired(&self, data:&SessionData) -> bool {
+        let now = SystemTime::now();
+        let older = |time:SystemTime, timeout:Option<Duration>| match timeout {
+            Some(timeout) => now.duration_since(time).map(|age| age > timeout).unwrap_or(false),
+            None => false,
+        };
+
+        older(data.accessed, self.idle_timeout) || older(data.created, self.absolute_timeout)
+    }
+
+    /*- Remove expired sessions now and then, without holding up the request -*/
+    fn cleanup(&self) {
+        {
+            let mut last_cleanup = self.last_cleanup.lock().unwrap_or_else(|e| e.into_inner());
+            if last_cleanup.elapsed() < CLEANUP_INTERVAL { return; };
+            *last_cleanup = Instant::now();
+        };
+
+        let sessions = self.clone();
+        thread::spawn(move || sessions.store.cleanup(&|data| sessions.expired(data)));
+    }
+}
+
+/*- 32 random bytes, url safe -*/
+fn new_id() -> String {
+    base64::encode_config(rand::random::<[u8; 32]>(), base64::URL_SAFE_NO_PAD)
+}
+
+/*- Cookies are sent by the client, so anything else is never passed to the store -*/
+fn valid_id(id:&str) -> bool {
+    id.len() == 43 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The session of a request, from `request.session()`. Clones are the same session.
+/// Values are stored as strings, and converted with `FromStr` and `ToString`
+/// # Examples
+/// ```ignore
+/// fn login(request:&Request) -> Response {
+///     let session = request.session().unwrap();
+///     session.regenerate();
+///     session.set("user_id", 42);
+///     Response::new(200)
+/// }
+///
+/// fn profile(request:&Request) -> Response {
+///     match request.session().and_then(|session| session.get::<u64>("user_id")) {
+///         Some(user_id) => Response::content(200, ResponseType::Text, format!("user {}", user_id)),
+///         None => Response::new(401),
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Session {
+    inner:Arc<Mutex<State>>,
+}
+
+struct State {
+    sessions:Sessions,
+    /*- The id the request was sent with -*/
+    cookie:Option<String>,
+    /*- Loaded on first use, so requests that don't use the session never touch the store -*/
+    loaded:Option<Loaded>,
+}
+
+struct Loaded {
+    /*- None until the session is saved for the first time -*/
+    id:Option<String>,
+    data:SessionData,
+    regenerate:bool,
+    destroy:bool,
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+        /*- The id is a secret, leave it out of logs -*/
+        f.write_str("Session")
+    }
+}
+
+impl Session {
+    pub(crate) fn new(sessions:Sessions, cookie:Option<String>) -> Session {
+        Session { inner: Arc::new(Mutex::new(State { sessions, cookie, loaded: None })) }
+    }
+
+    /*- Run `f` with the session, loading it from the store the first time -*/
+    fn with<T>(&self, f:impl FnOnce(&mut Loaded) -> T) -> T {
+        let mut state = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let State { sessions, cookie, loaded } = &mut *state;
+
+        let loaded = loaded.get_or_insert_with(|| {
+            sessions.cleanup();
+
+            let stored = cookie.as_deref()
+                .filter(|id| valid_id(id))
+                .and_then(|id| sessions.store.load(id).map(|data| (id, data)));
+
+            match stored {
+                Some((id, data)) if !sessions.expired(&data) => Loaded { id: Some(id.to_string()), data, regenerate: false, destroy: false },
+                Some((id, _)) => {
+                    sessions.store.remove(id).unwrap_or(());
+                    Loaded { id: None, data: SessionData::new(), regenerate: false, destroy: false }
+                },
+                None => Loaded { id: None, data: SessionData::new(), regenerate: false, destroy: false },
+            }
+        });
+
+        f(loaded)
+    }
+
+    /// The session id, `None` if the session hasn't been saved yet
+    pub fn id(&self) -> Option<String> { self.with(|loaded| loaded.id.clone()) }
+
+    /// Whether the session is new to this request
+    pub fn is_new(&self) -> bool { self.id().is_none() }
+
+    /// A value, converted to `T`. `None` if it's missing or doesn't parse
+    pub fn get<T:FromStr>(&self, key:&str) -> Option<T> {
+        self.with(|loaded| loaded.data.values.get(key).and_then(|value| value.parse().ok()))
+    }
+
+    pub fn set<T:ToString>(&self, key:&str, value:T) {
+        self.with(|loaded| {
+            loaded.data.values.insert(key.to_string(), value.to_string());
+            loaded.destroy = false;
+        });
+    }
+
+    pub fn remove(&self, key:&str) { self.with(|loaded| { loaded.data.values.remove(key); }); }
+    pub fn contains(&self, key:&str) -> bool { self.with(|loaded| loaded.data.values.contains_key(key)) }
+
+    /// Every key and value of the session
+    pub fn values(&self) -> HashMap<String, String> { self.with(|loaded| loaded.data.values.clone()) }
+
+    /// Remove every value, but keep the session
+    pub fn clear(&self) { self.with(|loaded| loaded.data.values.clear()); }
+
+    /// Give the session a new id when it's saved, keeping its values. Call this when
+    /// a user logs in, so that an id someone else knew before doesn't become logged in
+    pub fn regenerate(&self) { self.with(|loaded| loaded.regenerate = true); }
+
+    /// Remove the session from the store, and its cookie from the client
+    pub fn destroy(&self) {
+        self.with(|loaded| {
+            loaded.data = SessionData::new();
+            loaded.destroy = true;
+        });
+    }
+
+    /*- Save the session and set its cookie. Called once the response is done -*/
+    pub(crate) fn commit(&self, response:&mut Response) {
+        let mut state = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let State { sessions, loaded, .. } = &mut *state;
+        let loaded = match loaded { Some(loaded) => loaded, None => return };
+
+        let result = (|| -> io::Result<()> {
+            if loaded.destroy {
+                if let Some(id) = loaded.id.take() {
+                    sessions.store.remove(&id)?;
+                    response.headers.push((String::from("Set-Cookie"), sessions.cookie.clone().removal().to_string()));
+                };
+                return Ok(());
+            };
+
+            /*- Empty sessions aren't worth a cookie -*/
+            if loaded.id.is_none() && loaded.data.values.is_empty() { return Ok(()); };
+
+            if loaded.regenerate {
+                if let Some(id) = loaded.id.take() { sessions.store.remove(&id)?; };
+                loaded.regenerate = false;
+            };
+
+            /*- Every save counts as activity for the idle timeout -*/
+            loaded.data.accessed = SystemTime::now();
+            let id = match &loaded.id {
+                Some(id) => id.clone(),
+                None => {
+                    let id = new_id();
+                    let mut cookie = sessions.cookie.clone();
+                    cookie.value = id.clone();
+                    response.headers.push((String::from("Set-Cookie"), cookie.to_string()));
+                    loaded.id = Some(id.clone());
+                    id
+                },
+            };
+
+            sessions.store.save(&id, &loaded.data)
+        })();
+
+        if let Err(error) = result {
+            tracing::error!(%error, "couldn't save the session");
+        };
+    }
+}
+
+impl Request {
+
+    /// The session of the request, `None` if `ServerOptions::sessions` isn't set
+    pub fn session(&self) -> Option<Session> {
+        self.session.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /*- The id a response sets the session cookie to, if it does -*/
+    fn set_cookie(response:&Response) -> Option<String> {
+        let header = response.get_header("Set-Cookie")?;
+        let (pair, _) = header.split_once(';').unwrap_or((header, ""));
+        pair.strip_prefix("session=").map(String::from)
+    }
+
+    /*- Run a request with the session cookie, and commit what `handler` did -*/
+    fn request(sessions:&Sessions, cookie:Option<&str>, handler:impl FnOnce(&Session)) -> Response {
+        let session = Session::new(sessions.clone(), cookie.map(String::from));
+        let mut response = Response::new(200);
+        handler(&session);
+        session.commit(&mut response);
+        response
+    }
+
+    fn ago(secs:u64) -> SystemTime { SystemTime::now() - Duration::from_secs(secs) }
+
+    #[test]
+    fn sessions_expire_when_idle_or_too_old() {
+        let sessions = Sessions::new(MemoryStore::new())
+            .idle_timeout(Duration::from_secs(60))
+            .absolute_timeout(Duration::from_secs(3600));
+        let data = |created, accessed| SessionData { values: HashMap::new(), created: ago(created), accessed: ago(accessed) };
+
+        assert!(!sessions.expired(&data(0, 0)));
+        assert!(!sessions.expired(&data(3000, 30)));
+        assert!(sessions.expired(&data(3000, 61)));
+        assert!(sessions.expired(&data(3601, 0)));
+
+        let unlimited = Sessions { idle_timeout: None, absolute_timeout: None, ..sessions };
+        assert!(!unlimited.expired(&data(1_000_000, 1_000_000)));
+    }
+
+    #[test]
+    fn expired_sessions_are_not_loaded() {
+        let store = MemoryStore::new();
+        let sessions = Sessions::new(store.clone()).idle_timeout(Duration::from_secs(60));
+        let id = new_id();
+        let mut data = SessionData::new();
+        data.values.insert(String::from("user_id"), String::from("42"));
+        data.accessed = ago(61);
+        store.save(&id, &data).unwrap();
+
+        let response = request(&sessions, Some(&id), |session| {
+            assert!(session.is_new());
+            assert_eq!(session.get::<u64>("user_id"), None);
+            session.set("theme", "dark");
+        });
+
+        let new = set_cookie(&response).unwrap();
+        assert_ne!(new, id);
+        assert!(store.load(&id).is_none());
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn sessions_are_saved_and_loaded() {
+        let store = MemoryStore::new();
+        let sessions = Sessions::new(store.clone());
+
+        /*- Nothing set, nothing saved -*/
+        let response = request(&sessions, None, |session| assert_eq!(session.get::<String>("a"), None));
+        assert_eq!(response.get_header("Set-Cookie"), None);
+        assert!(store.is_empty());
+
+        let response = request(&sessions, None, |session| session.set("user_id", 42));
+        let header = response.get_header("Set-Cookie").unwrap().to_string();
+        assert!(header.ends_with("; Path=/; HttpOnly; SameSite=Lax"), "{}", header);
+        let id = set_cookie(&response).unwrap();
+        assert!(valid_id(&id));
+
+        /*- The cookie is only sent when the id changes -*/
+        let response = request(&sessions, Some(&id), |session| {
+            assert!(!session.is_new());
+            assert_eq!(session.get::<u64>("user_id"), Some(42));
+            session.set("visits", 2);
+        });
+        assert_eq!(response.get_header("Set-Cookie"), None);
+        assert_eq!(store.load(&id).unwrap().values.len(), 2);
+    }
+
+    #[test]
+    fn unknown_ids_get_a_new_one() {
+        let store = MemoryStore::new();
+        let sessions = Sessions::new(store.clone());
+
+        /*- An id the server never made (or an attacker picked) isn't adopted -*/
+        let chosen = "A".repeat(43);
+        let response = request(&sessions, Some(&chosen), |session| {
+            assert!(session.is_new());
+            session.set("user_id", 42);
+        });
+        let id = set_cookie(&response).unwrap();
+        assert_ne!(id, chosen);
+        assert!(store.load(&chosen).is_none());
+
+        /*- Ids that aren't ids never reach the store -*/
+        let response = request(&sessions, Some("../../etc/passwd"), |session| assert!(session.is_new()));
+        assert_eq!(response.get_header("Set-Cookie"), None);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn regenerate_replaces_the_id() {
+        let store = MemoryStore::new();
+        let sessions = Sessions::new(store.clone());
+        let old = set_cookie(&request(&sessions, None, |session| session.set("cart", "3 apples"))).unwrap();
+
+        let response = request(&sessions, Some(&old), |session| {
+            session.regenerate();
+            session.set("user_id", 42);
+        });
+
+        let new = set_cookie(&response).unwrap();
+        assert_ne!(new, old);
+        assert!(store.load(&old).is_none());
+        let data = store.load(&new).unwrap();
+        assert_eq!(data.values.get("cart").map(String::as_str), Some("3 apples"));
+        assert_eq!(data.values.get("user_id").map(String::as_str), Some("42"));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn destroy_removes_the_session_and_its_cookie() {
+        let store = MemoryStore::new();
+        let sessions = Sessions::new(store.clone());
+        let id = set_cookie(&request(&sessions, None, |session| session.set("user_id", 42))).unwrap();
+
+        let response = request(&sessions, Some(&id), |session| session.destroy());
+        assert_eq!(
+            response.get_header("Set-Cookie"),
+            Some("session=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT; HttpOnly; SameSite=Lax")
+        );
+        assert!(store.is_empty());
+
+        /*- Destroying a session that was never saved sends nothing -*/
+        let response = request(&sessions, None, |session| session.destroy());
+        assert_eq!(response.get_header("Set-Cookie"), None);
+    }
+
+    #[test]
+    fn file_store() {
+        let dir = env::temp_dir().join(format!("fastserve-sessions-{}", std::process::id()));
+        fs::remove_dir_all(&dir).unwrap_or(());
+        let store = FileStore::new(&dir).unwrap();
+
+        let mut data = SessionData::new();
+        data.values.insert(String::from("a=b"), String::from("100%\nnew line"));
+        data.values.insert(String::from("empty"), String::new());
+        let id = new_id();
+        store.save(&id, &data).unwrap();
+
+        /*- Times are kept in whole seconds -*/
+        let loaded = store.load(&id).unwrap();
+        assert_eq!(loaded.values, data.values);
+        assert_eq!(loaded.created.duration_since(UNIX_EPOCH).unwrap().as_secs(), data.created.duration_since(UNIX_EPOCH).unwrap().as_secs());
+
+        /*- Only the session is left, no temp files -*/
+        let files:Vec<String> = fs::read_dir(&dir).unwrap().flatten().map(|entry| entry.file_name().to_string_lossy().to_string()).collect();
+        assert_eq!(files, vec![id.clone()]);
+
+        let old = new_id();
+        store.save(&old, &SessionData { accessed: ago(3600), ..SessionData::new() }).unwrap();
+        fs::write(dir.join("not-a-session"), "0 0\n").unwrap();
+        store.cleanup(&|data| data.accessed < ago(60));
+        assert!(store.load(&old).is_none());
+        assert!(store.load(&id).is_some());
+        assert!(dir.join("not-a-session").exists());
+
+        store.remove(&id).unwrap();
+        store.remove(&id).unwrap();
+        assert!(store.load(&id).is_none());
+
+        fs::remove_dir_all(&dir).unwrap_or(());
+    }
+}
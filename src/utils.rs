@@ -1,12 +1,16 @@
-#![allow(deprecated, dead_code)]
+#![allow(deprecated, dead_code, clippy::needless_return)]
 
 use std::collections::HashMap;
 use std::net::TcpStream;
-use std::io::Write;
-use std::path::Path;
+use std::io::{ Write, IsTerminal };
+use crate::response::Response;
+use crate::mime::guess_mime;
+use crate::headers::header_lines;
+use std::time::{ SystemTime, UNIX_EPOCH };
 use lazy_static::lazy_static;
 use std::hash::{ Hash, Hasher };
 use std::collections::hash_map::DefaultHasher;
+use sha2::{ Digest, Sha256 };
 use termcolor::{ Color, ColorChoice, ColorSpec, StandardStream, WriteColor };
 
 /*- Static mutable variables -*/
@@ -46,7 +50,9 @@ lazy_static! {
     ];
 }
 
-/// The get_header function can either take multiple header requests as input, or just one
+/// The get_header function can either take multiple header requests as input, or just one.
+/// Kept for compatibility, `HeaderMap` (or `Request::headers`) handles duplicate headers
+/// and has typed accessors
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum HeaderReturn<'l> {
@@ -58,76 +64,46 @@ pub enum HeaderReturn<'l> {
 }
 
 /// Parse headers by using a request-string as input, and specify what headers you want returned
-/// By using ```HeaderReturn``` you can specify if you either want all headers back or just some
+/// By using ```HeaderReturn``` you can specify if you either want all headers back or just some.
+/// Names are case-insensitive, and only the head of the request is looked at. When a header is
+/// sent more than once, the first value is returned. `Values` and `None` give `None` back
 pub fn parse_headers<'a>(request:&'a str, header:HeaderReturn<'a>) -> HeaderReturn<'a> {
-    /*- Get the headers from the request -*/
-    let header_strings = request.split("\n");
-
-    /*- A hasmap of all heders -*/
-    let mut headers:HashMap<&str, &str> = HashMap::new();
-
-    /*- We want to split by the first colon, if we
-            don't do that and users start inputting colons
-            in their header string, it'll lead to an error -*/
-    for header in header_strings {
-
-        /*- Loop through every char -*/
-        'charLoop: for (i, c) in header.chars().enumerate() {
-            /*- If we find a colon, split the string -*/
-            if c == ':' {
-                /*- Get the key and value -*/
-                let key = &header[0..i];
-                let value = &header[i+1..];
-
-                /*- Add the key and value to the headers -*/
-                headers.insert(key, value);
-
-                /*- Break out of the loop -*/
-                break 'charLoop;
-            };
-        };
+    let get = |name:&str| -> &'a str {
+        header_lines(request).find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value).unwrap_or("")
     };
 
     /*- See what type of header the user wants -*/
     match header {
-        HeaderReturn::Single(v) => {
-            return HeaderReturn::Single(
-                headers.get(v).unwrap_or(&"").trim()
-            );
-        },
-        HeaderReturn::Multiple(v) => {
-
-            /*- Header "queue" -*/
-            let headers_to_get:Vec<&str> = v.into_iter().collect::<Vec<&str>>();
-
-            /*- Return the headers -*/
-            return HeaderReturn::Multiple(
-                headers_to_get.into_iter().map(|v| {
-                    headers.get(v).unwrap_or(&"").trim()
-                }).collect::<Vec<&str>>()
-            );
-        },
+        HeaderReturn::Single(name) => HeaderReturn::Single(get(name)),
+        HeaderReturn::Multiple(names) => HeaderReturn::Multiple(names.into_iter().map(get).collect()),
         HeaderReturn::All => {
-            return HeaderReturn::Values(
-                /*- Trim all values -*/
-                headers.into_iter().map(|(k, v)| {
-                    (k, v.trim())
-                }).collect::<HashMap<&str, &str>>()
-            );
+            let mut values:HashMap<&str, &str> = HashMap::new();
+            for (key, value) in header_lines(request) {
+                values.entry(key).or_insert(value);
+            };
+            HeaderReturn::Values(values)
         },
-        HeaderReturn::Values(_) => panic!("Can't get values - Values is read only"),
-        HeaderReturn::None =>      panic!("Can't get None - None is read only"),
-    };
+        HeaderReturn::Values(_) | HeaderReturn::None => HeaderReturn::None,
+    }
 }
 
-#[derive(Debug)]
+/// The content type of a response. Use `Custom` for anything that
+/// isn't listed, like `ResponseType::Custom("text/event-stream".into())`
+#[derive(Debug, Clone, PartialEq)]
 pub enum ResponseType {
     Text,
     Json,
     Html,
-    Image(ResponseTypeImage)
+    Image(ResponseTypeImage),
+    Css,
+    JavaScript,
+    Xml,
+    /// Raw bytes, `application/octet-stream`
+    Binary,
+    /// Any mime type, sent as-is
+    Custom(String),
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ResponseTypeImage {
     Jpeg,
     Png,
@@ -135,10 +111,47 @@ pub enum ResponseTypeImage {
     Webp,
     Svg,
 }
+
+impl ResponseType {
+    /// The value used for the `Content-Type` header
+    pub fn content_type(&self) -> &str {
+        match self {
+            ResponseType::Text => "text/plain",
+            ResponseType::Json => "application/json",
+            ResponseType::Html => "text/html",
+            ResponseType::Image(c) => {
+                match c {
+                    ResponseTypeImage::Jpeg => "image/jpeg",
+                    ResponseTypeImage::Png => "image/png",
+                    ResponseTypeImage::Gif => "image/gif",
+                    ResponseTypeImage::Webp => "image/webp",
+                    ResponseTypeImage::Svg => "image/svg+xml",
+                }
+            },
+            ResponseType::Css => "text/css",
+            ResponseType::JavaScript => "text/javascript",
+            ResponseType::Xml => "application/xml",
+            ResponseType::Binary => "application/octet-stream",
+            ResponseType::Custom(mime) => mime,
+        }
+    }
+}
+
+impl From<&str> for ResponseType {
+    fn from(mime:&str) -> ResponseType {
+        ResponseType::Custom(mime.to_string())
+    }
+}
+
+/// Get the message of a status code, like `Not Found` for 404
+pub fn status_message(status:u16) -> &'static str {
+    STATUS_CODES.iter().find(|&x| x.0 == &status).unwrap_or(&(&0u16, "Internal error - Missing status code")).1
+}
+
 ///
 /// Return a http response containing the status, and optionally some content
 /// # Examples
-/// ```
+/// ```ignore
 /// respond(&mut stream, 200u16, Some(ResponseType::Text), "Hello World");
 /// ```
 /// 
@@ -147,48 +160,21 @@ pub fn respond(
     status:u16,
     response_type:Option<ResponseType>,
     content:Option<&str>
-) -> () {
-
-    /*- Get the status string -*/
-    let status_msg = STATUS_CODES.iter().find(|&x| x.0 == &status).unwrap_or(&(&0u16, "Internal error - Missing status code")).1;
-
-    /*- Get the response type -*/
-    let response_type = match response_type {
-        Some(ResponseType::Text) => "text/plain",
-        Some(ResponseType::Json) => "application/json",
-        Some(ResponseType::Html) => "text/html",
-        Some(ResponseType::Image(c)) => {
-            match c {
-                ResponseTypeImage::Jpeg => "image/jpeg",
-                ResponseTypeImage::Png => "image/png",
-                ResponseTypeImage::Gif => "image/gif",
-                ResponseTypeImage::Webp => "image/webp",
-                ResponseTypeImage::Svg => "image/svg+xml",
-            }
-        }
-        None => "text/plain",
-    };
-    
+) {
+
     /*- Get the content exists -*/
-    if let Some(c) = content {
-        /*- Write the status to the stream -*/
-        stream.write(
-            format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nContent-Type: {}\r\n\r\n{}", status, c.len(), response_type, c).as_bytes()
-        ).unwrap();
-    }else {
-        /*- Write the status to the stream -*/
-        stream.write(
-            format!("HTTP/1.1 {}\r\n\r\n{} {}", status, status, status_msg).as_bytes()
-        ).unwrap();
+    let response = match content {
+        Some(c) => Response::content(status, response_type.unwrap_or(ResponseType::Text), c),
+        None => Response::new(status),
     };
 
-    /*- Flush the stream -*/
-    stream.flush().unwrap();
+    /*- Write the status to the stream -*/
+    response.write_to(stream);
 }
 
 /// Quick function to respond with a message saying that some headers might be missing
 /// # Examples
-/// ```
+/// ```ignore
 /// if !expect_headers(&mut stream, &headers, vec!["Content-Type", "Content-Length"]) {
 ///     return;
 /// };
@@ -202,17 +188,18 @@ pub fn expect_headers(
     /*- Quick way of sending a message of which headers that are missing -*/
     fn get_missing_response(missing:&Vec<&str>) -> String { format!("Missing headers: {:?}", missing) }
 
-    /*- Check what type of headers we got as input -*/
-    match headers {
-        HeaderReturn::Values(v) => {
-            for request in &required {
-                if !v.contains_key(request) {
-                    respond(stream, 400, Some(ResponseType::Text), Some(&get_missing_response(&required)));
-                    return false;
-                };
-            };
-        },
-        _ => panic!("Expected HeaderReturn::Values"),
+    /*- Anything but Values has no headers to check -*/
+    let missing:Vec<&str> = match headers {
+        HeaderReturn::Values(values) => required.iter()
+            .filter(|name| !values.keys().any(|key| key.eq_ignore_ascii_case(name)))
+            .copied()
+            .collect(),
+        _ => required.clone(),
+    };
+
+    if !missing.is_empty() {
+        respond(stream, 400, Some(ResponseType::Text), Some(&get_missing_response(&missing)));
+        return false;
     };
 
     /*- Return that all headers are specified -*/
@@ -228,14 +215,16 @@ fn reset_terminal_color(stdout: &mut StandardStream) {
             .unwrap();
 }
 
-/// Print a response with colors
+/// Print a response with colors. Colors are only used
+/// if stdout is a terminal, so they don't end up in log files
 /// # Examples
-/// ```
+/// ```ignore
 /// log(Color::Green, "Hello World");
 /// ```
 pub fn log(clr:Color, msg:&str) {
     /*- Set new standard output -*/
-    let mut stdout = StandardStream::stdout(ColorChoice::Always);
+    let choice = if std::io::stdout().is_terminal() { ColorChoice::Auto } else { ColorChoice::Never };
+    let mut stdout = StandardStream::stdout(choice);
 
     /*- Set the color to the inputted one -*/
     stdout.set_color(
@@ -263,35 +252,88 @@ pub(crate) fn hash<H: Hash>(hashval:&H,u:bool) -> String {
     else { return format!("{:x}", hasher.finish()); }
 }
 
+/*- A strong ETag for some content. Sha-256 instead of `hash`, since the
+    output of DefaultHasher can change between Rust releases -*/
+pub(crate) fn etag(content:&[u8]) -> String {
+    let digest = Sha256::digest(content);
+    let hex:String = digest[..16].iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// Guess the response type of a path by its extension, from the same table
+/// static files use. Mime types without their own variant become
+/// `ResponseType::Custom`, unknown extensions `Text`
 pub fn guess_response_type(path:&str) -> ResponseType {
-    let path:&Path = Path::new(path);
-
-    /*- Check extensions -*/
-    match path.extension() {
-        Some(ext) => {
-            match ext.to_str() {
-                /*- Html -*/
-                Some("html") => return ResponseType::Html,
-                Some("htm")  => return ResponseType::Html,
-
-                /*- Json -*/
-                Some("json") => return ResponseType::Json,
-                Some("yml")  => return ResponseType::Json,
-                Some("yaml") => return ResponseType::Json,
-
-                /*- Images -*/
-                Some("gif")  => return ResponseType::Image(ResponseTypeImage::Gif),
-                Some("png")  => return ResponseType::Image(ResponseTypeImage::Png),
-                Some("jpg")  => return ResponseType::Image(ResponseTypeImage::Jpeg),
-                Some("jpeg") => return ResponseType::Image(ResponseTypeImage::Jpeg),
-                Some("webp") => return ResponseType::Image(ResponseTypeImage::Webp),
-                Some("svg")  => return ResponseType::Image(ResponseTypeImage::Svg),
- 
-                /*- Text -*/
-                Some(_)   => return ResponseType::Text,
-                None      => return ResponseType::Text,
-            };
-        },
+    let mime = match guess_mime(path) {
+        Some(mime) => mime,
         None => return ResponseType::Text,
     };
+
+    match mime {
+        "text/plain" => ResponseType::Text,
+        "text/html" => ResponseType::Html,
+        "application/json" => ResponseType::Json,
+        "image/gif" => ResponseType::Image(ResponseTypeImage::Gif),
+        "image/png" => ResponseType::Image(ResponseTypeImage::Png),
+        "image/jpeg" => ResponseType::Image(ResponseTypeImage::Jpeg),
+        "image/webp" => ResponseType::Image(ResponseTypeImage::Webp),
+        "image/svg+xml" => ResponseType::Image(ResponseTypeImage::Svg),
+        "text/css" => ResponseType::Css,
+        "text/javascript" => ResponseType::JavaScript,
+        "application/xml" => ResponseType::Xml,
+        "application/octet-stream" => ResponseType::Binary,
+        mime => ResponseType::Custom(mime.to_string()),
+    }
+}
+
+/*- A point in time split up into UTC calendar parts, used
+    for log timestamps and http dates (without pulling in chrono) -*/
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct UtcTime {
+    pub year:i64,
+    pub month:u32,
+    pub day:u32,
+    pub hour:u32,
+    pub minute:u32,
+    pub second:u32,
+    /*- 0 is sunday -*/
+    pub weekday:u32,
+}
+
+pub(crate) const MONTHS:[&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+pub(crate) const WEEKDAYS:[&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+impl UtcTime {
+    pub fn from(time:SystemTime) -> UtcTime {
+        let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+        let (days, rest) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+        /*- Days to civil date, see http://howardhinnant.github.io/date_algorithms.html -*/
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        UtcTime {
+            year, month, day,
+            hour: (rest / 3600) as u32,
+            minute: (rest % 3600 / 60) as u32,
+            second: (rest % 60) as u32,
+            weekday: (days + 4).rem_euclid(7) as u32,
+        }
+    }
+}
+
+/// Format a time as a http date, like `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn http_date(time:SystemTime) -> String {
+    let t = UtcTime::from(time);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[t.weekday as usize], t.day, MONTHS[t.month as usize - 1], t.year, t.hour, t.minute, t.second
+    )
 }